@@ -6,38 +6,2584 @@
 
 use crate::parsing::ParsedFile;
 use crate::tree_traversal::{get_children, TraversalOrder};
+use regex::Regex;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
 use tree_sitter::Node;
 
-pub fn get_frames(parsed_file: &ParsedFile) -> Vec<Node> {
+/// Sectioning commands of beamer from the top level down.
+const SECTION_LEVELS: [&str; 4] = ["part", "section", "subsection", "subsubsection"];
+
+/// Environments whose content is never typeset as LaTeX code.
+const VERBATIM_ENVS: [&str; 9] = [
+    "comment",
+    "verbatim",
+    "verbatim*",
+    "Verbatim",
+    "BVerbatim",
+    "LVerbatim",
+    "lstlisting",
+    "minted",
+    "filecontents",
+];
+
+lazy_static! {
+    static ref NEWENVIRONMENT_REGEX: Regex =
+        Regex::new(r"\\(?:re)?newenvironment\*?\s*\{([^}]+)\}").unwrap();
+    static ref OVERLAY_REGEX: Regex = Regex::new(concat!(
+        r"(?P<pause>\\pause\b(?:\s*\[(?P<pause_to>\d+)\])?)",
+        r"|(?P<list>\\begin\s*\{(?:itemize|enumerate|description)\}",
+        r"(?:\s*\[\s*<(?P<list_spec>[^<>{}\\$\n]*)>\s*\])?)",
+        r"|(?P<list_end>\\end\s*\{(?:itemize|enumerate|description)\})",
+        r"|(?P<item>\\item\b(?:\s*<(?P<item_spec>[^<>{}\\$\n]*)>)?)",
+        r"|(?:\\[a-zA-Z]+\*?|\})\s*<(?P<spec>[^<>{}\\$\n]*)>",
+    ))
+    .unwrap();
+    static ref SECTION_REGEX: Regex =
+        Regex::new(r"\\(?:part|section|subsection|subsubsection|appendix)\b").unwrap();
+    static ref REFERENCE_REGEX: Regex = Regex::new(concat!(
+        r"\\(?P<command>[a-zA-Z]*ref|[a-zA-Z]*[cC]ite[a-zA-Z]*|printbibliography|bibliography",
+        r"|inserttotalframenumber|tableofcontents)\b\*?"
+    ))
+    .unwrap();
+    static ref BIBLIOGRAPHY_REGEX: Regex =
+        Regex::new(r"\\(?:bibliographystyle|bibliography|addbibresource)\b").unwrap();
+    static ref BBL_ENTRY_REGEX: Regex =
+        Regex::new(r"\\bibitem\s*(?:\[[^\]]*\])?\s*\{([^}]*)\}|\\entry\{([^}]*)\}").unwrap();
+    static ref BIBLATEX_REGEX: Regex =
+        Regex::new(r"\\usepackage\s*(?:\[([^\]]*)\])?\s*\{[^}]*\bbiblatex\b[^}]*\}").unwrap();
+    static ref SECTION_HOOK_REGEX: Regex =
+        Regex::new(r"\\AtBegin(?:Part|Section|Subsection|Subsubsection)\b").unwrap();
+    /// Themes, outer themes and commands that draw a navigation from the `.nav` file
+    static ref NAVIGATION_REGEX: Regex = Regex::new(concat!(
+        r"\\use(?:outer)?theme\s*(?:\[[^\]]*\])?\s*\{\s*(?:Antibes|Berkeley|Berlin|Copenhagen",
+        r"|Darmstadt|Dresden|Frankfurt|Goettingen|Hannover|Ilmenau|JuanLesPins|Luebeck|Malmoe",
+        r"|Marburg|Montpellier|PaloAlto|Singapore|Szeged|Warsaw|miniframes|shadow|sidebar",
+        r"|smoothbars|smoothtree|split|tree)\s*\}|\\insert[a-z]*navigation"
+    ))
+    .unwrap();
+    static ref TEMPLATE_REGEX: Regex = Regex::new(concat!(
+        r"\\(?:usebackgroundtemplate|setbeamertemplate|addtobeamertemplate|setbeamercolor",
+        r"|setbeamerfont|setbeamercovered|setbeamersize)\b"
+    ))
+    .unwrap();
+    static ref TITLE_PAGE_REGEX: Regex = Regex::new(r"\\(?:maketitle|titlepage)\b").unwrap();
+    static ref OVERLAY_VALUE_REGEX: Regex =
+        Regex::new(r"(?P<relative>[+.])(?:\((?P<offset>-?\d+)\))?|(?P<number>\d+)").unwrap();
+}
+
+/// Templates that beamer draws around the content of a frame. Compiled frames already show
+/// them, so they are cleared when the frames are reinserted into the document.
+const OUTER_TEMPLATES: [&str; 9] = [
+    "background canvas",
+    "background",
+    "headline",
+    "footline",
+    "navigation symbols",
+    "sidebar canvas left",
+    "sidebar canvas right",
+    "sidebar left",
+    "sidebar right",
+];
+
+/// How a frame was found by `extract_frames`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameSource {
+    SyntaxTree,
+    /// Found by regex splitting, e.g. because the syntax tree has errors in this region.
+    Fallback,
+    /// Synthesized for a frame that beamer adds without a frame in the source, see
+    /// `implicit_frames`.
+    Implicit,
+    /// Found in a file included with `\input`, see `included_frames`.
+    Included,
+}
+
+/// A frame of the presentation together with the metadata that can be read from its source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Byte range of the frame in the document
+    pub range: Range<usize>,
+    /// Source of the frame from `\begin{frame}` to `\end{frame}`
+    pub text: String,
+    /// Offset of the frame body in `text`, behind the arguments of the frame
+    pub body_start: usize,
+    pub options: FrameOptions,
+    /// `\frametitle` or the `{title}` argument of the frame
+    pub title: Option<String>,
+    /// `\framesubtitle` or the `{subtitle}` argument of the frame
+    pub subtitle: Option<String>,
+    /// Number of slides the overlay specifications and `\pause`s of the frame produce
+    pub overlays: usize,
+    pub source: FrameSource,
+    /// The sectioning commands in effect at the frame, from the part down, see `assign_sections`
+    pub sections: Vec<Section>,
+    /// Whether the frame comes after `\appendix`
+    pub appendix: bool,
+    /// Source of the template, color and font settings in the body of the document that are
+    /// in effect at the frame, see `assign_templates`
+    pub templates: String,
+    pub references: References,
+    /// The frame that an `\againframe` shows again
+    pub againframe: Option<AgainFrame>,
+    /// Text of the `\note`s of the frame
+    pub notes: Vec<String>,
+    /// Source of the `\note`s between the frame and the next one, see `attach_notes`
+    pub trailing_notes: String,
+    /// Name of the file included with `\input` that contains the frame and the byte range of
+    /// the frame in that file
+    pub included: Option<(String, Range<usize>)>,
+}
+
+/// The target of an `\againframe<overlay>{label}`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AgainFrame {
+    pub label: String,
+    /// Overlay specification of the `\againframe`
+    pub overlays: Option<String>,
+    /// Source of the labelled frame restricted to `overlays`, see `resolve_againframes`
+    pub text: Option<String>,
+}
+
+/// What a frame uses from the auxiliary files of a compile of the whole document.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct References {
+    /// Labels used with `\ref` and friends
+    pub labels: Vec<String>,
+    /// Keys used with `\cite` and friends
+    pub citations: Vec<String>,
+    /// Whether the frame typesets the bibliography
+    pub bibliography: bool,
+    /// Whether the frame uses `\inserttotalframenumber`
+    pub total_frames: bool,
+    /// Whether the frame typesets the `\tableofcontents`
+    pub table_of_contents: bool,
+}
+
+/// A `\part`, `\section`, `\subsection` or `\subsubsection` command of the document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Section {
+    /// Index of the command in `SECTION_LEVELS`
+    pub level: usize,
+    /// Value of the counter after the command or `None` for starred commands
+    pub number: Option<usize>,
+    /// Source of the command, e.g. `\section[Short]{Long title}`
+    pub text: String,
+    /// The long title of the command as it appears in the table of contents
+    pub title: String,
+}
+
+impl Section {
+    /// Returns the short title of the command in `[...]` as shown in navigation bars, or the
+    /// long title if it has none.
+    pub fn short_title(&self) -> &str {
+        let (_, pos) = control_word(&self.text, 0);
+        let start = skip_whitespace(&self.text, pos);
+        match skip_group(&self.text, start, b'[', b']') {
+            Some(end) => self.text[start + 1..end - 1].trim(),
+            None => &self.title,
+        }
+    }
+}
+
+/// The options given to a frame in `[...]`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameOptions {
+    pub fragile: bool,
+    pub allowframebreaks: bool,
+    pub plain: bool,
+    pub noframenumbering: bool,
+    pub label: Option<String>,
+    /// Vertical alignment of the frame: `t`, `c` or `b`
+    pub alignment: Option<char>,
+}
+
+impl Frame {
+    /// Reads the frame at `range` of `content`.
+    pub fn new(content: &str, range: Range<usize>, source: FrameSource) -> Frame {
+        let text = &content[range.clone()];
+        let mut frame = Frame {
+            range,
+            text: text.to_string(),
+            body_start: 0,
+            options: FrameOptions::default(),
+            title: None,
+            subtitle: None,
+            overlays: 1,
+            source,
+            sections: Vec::new(),
+            appendix: false,
+            templates: String::new(),
+            references: References::default(),
+            againframe: None,
+            notes: Vec::new(),
+            trailing_notes: String::new(),
+            included: None,
+        };
+
+        if text.starts_with("\\againframe") {
+            let mut pos = "\\againframe".len();
+            let mut overlays = None;
+            loop {
+                let start = skip_whitespace(text, pos);
+                if let Some(end) = skip_group(text, start, b'<', b'>') {
+                    overlays = Some(text[start + 1..end - 1].trim().to_string());
+                    pos = end;
+                } else if let Some(end) = skip_group(text, start, b'[', b']') {
+                    pos = end;
+                } else {
+                    break;
+                }
+            }
+            let start = skip_whitespace(text, pos);
+            if let Some(end) = skip_group(text, start, b'{', b'}') {
+                frame.againframe = Some(AgainFrame {
+                    label: text[start + 1..end - 1].trim().to_string(),
+                    overlays,
+                    text: None,
+                });
+            }
+            frame.body_start = text.len();
+            return frame;
+        }
+
+        let mut pos;
+        let env = if text.starts_with("\\begin") {
+            let name_start = skip_whitespace(text, "\\begin".len());
+            pos = skip_group(text, name_start, b'{', b'}').unwrap_or(name_start);
+            text[name_start..pos].trim_matches(|c| c == '{' || c == '}')
+        } else {
+            pos = "\\frame".len();
+            "frame"
+        };
+        // custom frame environments have arguments of their own
+        if env == "frame" {
+            loop {
+                let start = skip_argument_space(text, pos);
+                if let Some(end) = skip_group(text, start, b'<', b'>') {
+                    pos = end;
+                } else if let Some(end) = skip_group(text, start, b'[', b']') {
+                    let argument = &text[start + 1..end - 1];
+                    if !argument.trim_start().starts_with('<') {
+                        frame.options = FrameOptions::parse(argument);
+                    }
+                    pos = end;
+                } else {
+                    break;
+                }
+            }
+            if text.starts_with("\\begin") {
+                let mut arguments = Vec::new();
+                while arguments.len() < 2 {
+                    let start = skip_argument_space(text, pos);
+                    match skip_group(text, start, b'{', b'}') {
+                        Some(end) => {
+                            arguments.push(normalize_text(&text[start + 1..end - 1]));
+                            pos = end;
+                        }
+                        None => break,
+                    }
+                }
+                let mut arguments = arguments.into_iter();
+                frame.title = arguments.next();
+                frame.subtitle = arguments.next();
+            }
+        }
+
+        frame.body_start = pos;
+        let body = &text[pos..];
+        let dead = dead_regions(body);
+        if frame.title.is_none() {
+            frame.title = find_macro_argument(body, "frametitle", &dead);
+        }
+        if frame.subtitle.is_none() {
+            frame.subtitle = find_macro_argument(body, "framesubtitle", &dead);
+        }
+        frame.overlays = count_overlays(body, &dead);
+        frame.references = find_references(body, &dead);
+        frame.notes = macro_arguments(body, "note", &dead).collect();
+        frame
+    }
+
+    /// Returns LaTeX code that repeats the sectioning commands of the frame with the same
+    /// counter values, so that headlines and `\insertsection` show the right sections when
+    /// the frame is compiled on its own. The hooks of `\AtBeginSection` and friends are cleared
+    /// as they would add frames.
+    pub fn section_replay(&self) -> String {
+        if self.sections.is_empty() && !self.appendix {
+            return String::new();
+        }
+        let mut replay = String::from(concat!(
+            "\\AtBeginPart{}\\AtBeginSection[]{}",
+            "\\AtBeginSubsection[]{}\\AtBeginSubsubsection[]{}\n"
+        ));
+        if self.appendix {
+            replay += "\\appendix\n";
+        }
+        for section in &self.sections {
+            if let Some(number) = section.number {
+                replay += &format!(
+                    "\\setcounter{{{}}}{{{}}}",
+                    SECTION_LEVELS[section.level],
+                    number - 1
+                );
+            }
+            replay += &section.text;
+            replay += "\n";
+        }
+        replay
+    }
+
+    /// Returns by how much the frame advances the `framenumber` counter when it produces
+    /// `pages` pages.
+    pub fn frame_count(&self, pages: usize) -> usize {
+        if self.options.noframenumbering {
+            0
+        } else if self.options.allowframebreaks {
+            // every page of a frame with breaks is a frame of its own
+            pages.max(1)
+        } else {
+            1
+        }
+    }
+
+    /// Returns LaTeX code that reinserts the compiled `pdf` of the frame with its `pages` into
+    /// the document. The pages keep their size and are not drawn over by the templates of the
+    /// theme, and the frame and page counters advance like for the frame itself.
+    pub fn reinsertion(&self, pdf: &str, pages: usize) -> String {
+        let mut templates = String::from("\\setbeamercolor{background canvas}{bg=}");
+        for template in OUTER_TEMPLATES.iter() {
+            templates += &format!("\\setbeamertemplate{{{}}}{{}}", template);
+        }
+        format!(
+            concat!(
+                "\\edef\\fasterbeamerframenumber{{\\the\\value{{framenumber}}}}",
+                "\\edef\\fasterbeamerpage{{\\the\\value{{page}}}}\n",
+                "{{{}\n\\includepdf[pages=-,noautoscale,fitpaper]{{{}}}\n}}",
+                "\\setcounter{{framenumber}}{{\\numexpr\\fasterbeamerframenumber+{}\\relax}}",
+                "\\setcounter{{page}}{{\\numexpr\\fasterbeamerpage+{}\\relax}}"
+            ),
+            templates,
+            pdf,
+            self.frame_count(pages),
+            pages
+        )
+    }
+
+    /// Returns the source that is compiled for the frame, followed by the `\note`s attached to
+    /// it. For `\againframe` this is the labelled frame it refers to.
+    pub fn compile_text(&self) -> String {
+        let text = match &self.againframe {
+            Some(AgainFrame {
+                text: Some(text), ..
+            }) => text,
+            _ => &self.text,
+        };
+        text.clone() + &self.trailing_notes
+    }
+
+    /// Returns the label of the frame or of the frame that an `\againframe` shows.
+    pub fn label(&self) -> Option<&str> {
+        match &self.againframe {
+            Some(againframe) => Some(&againframe.label),
+            None => self.options.label.as_deref(),
+        }
+    }
+
+    /// Returns the overlay that shows the source at byte `offset` of the frame text.
+    pub fn overlay_at(&self, offset: usize) -> usize {
+        let mut end = offset.max(self.body_start).min(self.text.len());
+        while !self.text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let body = &self.text[self.body_start..end];
+        count_overlays(body, &dead_regions(body)).min(self.overlays)
+    }
+
+    /// Returns the source of the frame restricted to the overlay `slide` with beamer's
+    /// `\begin{frame}<slide>`. Frames with an overlay restriction of their own and custom
+    /// frame environments cannot be restricted.
+    pub fn restrict_to_overlay(&self, slide: usize) -> Option<String> {
+        let text = self.compile_text();
+        let head = overlay_head(&text)?;
+        if text[head..].trim_start().starts_with('<') {
+            return None;
+        }
+        Some(format!("{}<{}>{}", &text[..head], slide, &text[head..]))
+    }
+
+    /// Returns whether the `change`d byte range of the document touches the frame. A change
+    /// right before or behind the frame does not. An empty change, i.e. a deletion, has to lie
+    /// strictly inside of the frame and an implicit frame strictly inside of the change.
+    pub fn is_changed_by(&self, change: &Range<usize>) -> bool {
+        let range = &self.range;
+        if change.is_empty() {
+            range.start < change.start && change.start < range.end
+        } else if range.is_empty() {
+            change.start < range.start && range.start < change.end
+        } else {
+            change.start < range.end && range.start < change.end
+        }
+    }
+
+    /// Returns a short description of the frame for log messages, e.g. `frame 3 "Results"`.
+    pub fn describe(&self, frame_idx: usize) -> String {
+        match (&self.title, &self.options.label) {
+            (Some(title), _) => format!("frame {} \"{}\"", frame_idx + 1, title),
+            (None, Some(label)) => format!("frame {} ({})", frame_idx + 1, label),
+            (None, None) => format!("frame {}", frame_idx + 1),
+        }
+    }
+}
+
+impl FrameOptions {
+    fn parse(options: &str) -> FrameOptions {
+        let mut result = FrameOptions::default();
+        for option in split_options(options) {
+            let (key, value) = match option.find('=') {
+                Some(i) => (option[..i].trim(), Some(option[i + 1..].trim())),
+                None => (option, None),
+            };
+            match key {
+                "fragile" => result.fragile = true,
+                "allowframebreaks" => result.allowframebreaks = true,
+                "plain" => result.plain = true,
+                "noframenumbering" => result.noframenumbering = true,
+                "label" => {
+                    result.label = value.map(|v| {
+                        v.trim_start_matches('{').trim_end_matches('}').trim().to_string()
+                    })
+                }
+                "t" | "c" | "b" => result.alignment = key.chars().next(),
+                _ => {}
+            }
+        }
+        result
+    }
+}
+
+/// Returns the end of `\begin{frame}` or `\frame` at the start of `text`, where beamer expects
+/// the overlay specification of the frame.
+fn overlay_head(text: &str) -> Option<usize> {
+    ["\\begin{frame}", "\\frame"]
+        .iter()
+        .find(|head| text.starts_with(*head))
+        .map(|head| head.len())
+}
+
+/// Returns how many of the `overlays` slides of a frame the overlay specification `spec`, like
+/// `3` or `1,3-`, selects. Incremental specifications and alternatives give `None`.
+fn count_spec_slides(spec: &str, overlays: usize) -> Option<usize> {
+    if spec.contains(['+', '|', '.']) {
+        return None;
+    }
+    let mut slides = BTreeSet::new();
+    for part in spec.split(',') {
+        // drop a mode like `beamer:`
+        let part = part.rsplit(':').next().unwrap_or(part).trim();
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => {
+                let first = match first.trim() {
+                    "" => 1,
+                    first => first.parse().ok()?,
+                };
+                match last.trim() {
+                    "" => (first, overlays.max(first)),
+                    last => (first, last.parse().ok()?),
+                }
+            }
+            None => {
+                let slide = part.parse().ok()?;
+                (slide, slide)
+            }
+        };
+        slides.extend((first..=last).filter(|&slide| slide > 0));
+    }
+    Some(slides.len().max(1))
+}
+
+/// Resolves the `\againframe`s among `frames` by the `label=` option of the frames they show.
+/// The overlay specification of an `\againframe` replaces the one of the labelled frame and
+/// decides how many slides it shows.
+pub fn resolve_againframes(frames: &mut [Frame]) {
+    let labels: HashMap<String, usize> = frames
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, f)| f.options.label.clone().map(|label| (label, idx)))
+        .collect();
+
+    for idx in 0..frames.len() {
+        let (label, overlays) = match &frames[idx].againframe {
+            Some(againframe) => (againframe.label.clone(), againframe.overlays.clone()),
+            None => continue,
+        };
+        let target = match labels.get(&label) {
+            Some(&target) => &frames[target],
+            None => {
+                warn!("\\againframe refers to unknown frame label {}", label);
+                continue;
+            }
+        };
+        let slides = overlays
+            .as_deref()
+            .and_then(|spec| count_spec_slides(spec, target.overlays))
+            .unwrap_or(target.overlays);
+        let text = match (overlay_head(&target.text), overlays) {
+            (Some(head), Some(overlays)) => {
+                // drop the overlay specification of the labelled frame
+                let start = skip_whitespace(&target.text, head);
+                let rest = skip_group(&target.text, start, b'<', b'>').unwrap_or(head);
+                format!("{}<{}>{}", &target.text[..head], overlays, &target.text[rest..])
+            }
+            _ => target.text.clone(),
+        };
+        let target = target.clone();
+        let frame = &mut frames[idx];
+        frame.overlays = slides;
+        frame.title = target.title;
+        frame.subtitle = target.subtitle;
+        frame.references = target.references;
+        frame.notes = target.notes;
+        if let Some(againframe) = &mut frame.againframe {
+            againframe.text = Some(text);
+        }
+    }
+}
+
+/// Returns `preamble` with the options of its beamer `\documentclass` rewritten for `mode`:
+/// `presentation`, `handout`, `trans` or `article`, which uses the article class with the
+/// beamerarticle package instead.
+pub fn set_document_mode(preamble: &str, mode: &str) -> String {
+    let dead = dead_regions(preamble);
+    let start = match preamble
+        .match_indices("\\documentclass")
+        .find(|(start, _)| !is_dead(&dead, *start))
+    {
+        Some((start, _)) => start,
+        None => return preamble.to_string(),
+    };
+    let (_, mut pos) = control_word(preamble, start);
+    let mut options = Vec::new();
+    let options_start = skip_whitespace(preamble, pos);
+    if let Some(end) = skip_group(preamble, options_start, b'[', b']') {
+        options = split_options(&preamble[options_start + 1..end - 1]);
+        pos = end;
+    }
+    let class_start = skip_whitespace(preamble, pos);
+    let end = match skip_group(preamble, class_start, b'{', b'}') {
+        Some(end) if preamble[class_start + 1..end - 1].trim() == "beamer" => end,
+        _ => {
+            warn!("--mode only applies to documents of the beamer class");
+            return preamble.to_string();
+        }
+    };
+
+    options.retain(|option| !["handout", "trans", "presentation"].contains(option));
+    let class = match mode {
+        "article" => "article}\n\\usepackage{beamerarticle",
+        "handout" | "trans" => {
+            options.push(mode);
+            "beamer"
+        }
+        _ => "beamer",
+    };
+    format!(
+        "{}\\documentclass[{}]{{{}}}{}",
+        &preamble[..start],
+        options.join(","),
+        class,
+        &preamble[end..]
+    )
+}
+
+/// Returns `preamble` with its live `\setbeameroption` commands about notes replaced by
+/// `\setbeameroption{option}`, e.g. `show notes on second screen`.
+pub fn set_notes_option(preamble: &str, option: &str) -> String {
+    let dead = dead_regions(preamble);
+    let mut result = String::new();
+    let mut copied = 0;
+    for (start, _) in preamble.match_indices("\\setbeameroption") {
+        if start < copied || is_dead(&dead, start) {
+            continue;
+        }
+        let (_, pos) = control_word(preamble, start);
+        let arg_start = skip_whitespace(preamble, pos);
+        if let Some(end) = skip_group(preamble, arg_start, b'{', b'}') {
+            if preamble[arg_start..end].contains("notes") {
+                result += &preamble[copied..start];
+                copied = end;
+            }
+        }
+    }
+    result += &preamble[copied..];
+    result + &format!("\n\\setbeameroption{{{}}}\n", option)
+}
+
+/// Returns the frame labels given to `\includeonlyframes` in `content`, if it is used.
+pub fn include_only_frames(content: &str) -> Option<Vec<String>> {
+    let dead = dead_regions(content);
+    content
+        .match_indices("\\includeonlyframes")
+        .filter(|(start, _)| !is_dead(&dead, *start))
+        .find_map(|(start, command)| {
+            let start = skip_whitespace(content, start + command.len());
+            let end = skip_group(content, start, b'{', b'}')?;
+            Some(
+                content[start + 1..end - 1]
+                    .split(',')
+                    .map(|label| label.trim().to_string())
+                    .filter(|label| !label.is_empty())
+                    .collect(),
+            )
+        })
+}
+
+/// Splits a comma separated option list at the commas that are not nested in braces.
+fn split_options(options: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in options.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                result.push(options[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    result.push(options[start..].trim());
+    result.retain(|option| !option.is_empty());
+    result
+}
+
+/// Skips the spaces that LaTeX allows between the arguments of a macro. A blank line ends
+/// the arguments.
+fn skip_argument_space(content: &str, pos: usize) -> usize {
+    let end = skip_whitespace(content, pos);
+    if content[pos..end].matches('\n').count() > 1 {
+        pos
+    } else {
+        end
+    }
+}
+
+/// Collapses all whitespace in `text` to single spaces.
+fn normalize_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Returns the mandatory argument of the first live `\name<overlay>[short]{argument}`.
+fn find_macro_argument(content: &str, name: &str, dead: &[Range<usize>]) -> Option<String> {
+    macro_arguments(content, name, dead).next()
+}
+
+/// Returns the mandatory arguments of all live `\name<overlay>[short]{argument}` commands.
+fn macro_arguments<'a>(
+    content: &'a str,
+    name: &'a str,
+    dead: &'a [Range<usize>],
+) -> impl Iterator<Item = String> + 'a {
+    macro_calls(content, name, dead).map(|(_, argument)| argument)
+}
+
+/// Returns the source ranges and the mandatory arguments of all live
+/// `\name<overlay>[short]{argument}` commands.
+fn macro_calls<'a>(
+    content: &'a str,
+    name: &'a str,
+    dead: &'a [Range<usize>],
+) -> impl Iterator<Item = (Range<usize>, String)> + 'a {
+    content
+        .match_indices('\\')
+        .filter(move |(start, _)| !is_dead(dead, *start))
+        .filter_map(move |(start, _)| {
+            let (word, mut pos) = control_word(content, start);
+            if word != name {
+                return None;
+            }
+            loop {
+                let arg_start = skip_whitespace(content, pos);
+                match skip_group(content, arg_start, b'<', b'>')
+                    .or_else(|| skip_group(content, arg_start, b'[', b']'))
+                {
+                    Some(end) => pos = end,
+                    None => break,
+                }
+            }
+            let arg_start = skip_whitespace(content, pos);
+            skip_group(content, arg_start, b'{', b'}')
+                .map(|end| (start..end, normalize_text(&content[arg_start + 1..end - 1])))
+        })
+}
+
+/// Returns the references of `content` that need the auxiliary files of a full compile.
+pub fn find_references(content: &str, dead: &[Range<usize>]) -> References {
+    let mut references = References::default();
+    for m in REFERENCE_REGEX.find_iter(content) {
+        if is_dead(dead, m.start()) {
+            continue;
+        }
+        let (command, mut pos) = control_word(content, m.start());
+        match command {
+            "href" => continue,
+            "inserttotalframenumber" => {
+                references.total_frames = true;
+                continue;
+            }
+            "tableofcontents" => {
+                references.table_of_contents = true;
+                continue;
+            }
+            "bibliography" | "printbibliography" => {
+                references.bibliography = true;
+                continue;
+            }
+            _ => {}
+        }
+        let keys = if command.to_lowercase().contains("cite") {
+            &mut references.citations
+        } else {
+            &mut references.labels
+        };
+        if content[pos..].starts_with('*') {
+            pos += 1;
+        }
+        // skip pre- and postnotes of citations
+        loop {
+            let start = skip_whitespace(content, pos);
+            match skip_group(content, start, b'[', b']') {
+                Some(end) => pos = end,
+                None => break,
+            }
+        }
+        let start = skip_whitespace(content, pos);
+        if let Some(end) = skip_group(content, start, b'{', b'}') {
+            for key in content[start + 1..end - 1].split(',') {
+                let key = key.trim().to_string();
+                if !key.is_empty() && !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+    }
+    references
+}
+
+/// Returns the program that processes the bibliography of the document in `content`, if any.
+pub fn bibliography_tool(content: &str) -> Option<&'static str> {
+    let dead = dead_regions(content);
+    if let Some(cap) = BIBLATEX_REGEX
+        .captures_iter(content)
+        .find(|cap| !is_dead(&dead, cap.get(0).unwrap().start()))
+    {
+        let bibtex_backend = cap.get(1).is_some_and(|options| {
+            split_options(options.as_str())
+                .iter()
+                .any(|option| option.replace(' ', "").starts_with("backend=bibtex"))
+        });
+        return Some(if bibtex_backend { "bibtex" } else { "biber" });
+    }
+    content
+        .match_indices("\\bibliography{")
+        .find(|(start, _)| !is_dead(&dead, *start))
+        .map(|_| "bibtex")
+}
+
+/// Returns the live `\bibliographystyle`, `\bibliography` and `\addbibresource` commands of
+/// `content` as the name of the command, its source and its argument.
+pub fn bibliography_commands(content: &str) -> Vec<(&str, &str, &str)> {
+    let dead = dead_regions(content);
+    BIBLIOGRAPHY_REGEX
+        .find_iter(content)
+        .filter(|m| !is_dead(&dead, m.start()))
+        .filter_map(|m| {
+            let (command, mut pos) = control_word(content, m.start());
+            loop {
+                let start = skip_whitespace(content, pos);
+                match skip_group(content, start, b'[', b']') {
+                    Some(end) => pos = end,
+                    None => break,
+                }
+            }
+            let start = skip_whitespace(content, pos);
+            let end = skip_group(content, start, b'{', b'}')?;
+            Some((
+                command,
+                &content[m.start()..end],
+                content[start + 1..end - 1].trim(),
+            ))
+        })
+        .collect()
+}
+
+/// Returns the bibliography databases of `content` from `\bibliography` and
+/// `\addbibresource`.
+pub fn bibliography_files(content: &str) -> Vec<String> {
+    let mut files = Vec::new();
+    for (command, _, argument) in bibliography_commands(content) {
+        match command {
+            "bibliography" => files.extend(argument.split(',').map(|file| {
+                let file = file.trim();
+                if file.ends_with(".bib") {
+                    file.to_string()
+                } else {
+                    format!("{}.bib", file)
+                }
+            })),
+            "addbibresource" => files.push(argument.to_string()),
+            _ => {}
+        }
+    }
+    files
+}
+
+/// Returns the entries of the `.bbl` file `bbl` for the citation `keys`, the `\bibitem`s of
+/// BibTeX or the `\entry`s of biblatex.
+pub fn bbl_entries(bbl: &str, keys: &[String]) -> String {
+    let matches: Vec<_> = BBL_ENTRY_REGEX.captures_iter(bbl).collect();
+    let mut entries = String::new();
+    for (i, cap) in matches.iter().enumerate() {
+        let key = cap.get(1).or_else(|| cap.get(2)).unwrap().as_str().trim();
+        if keys.iter().any(|k| k == key) {
+            let start = cap.get(0).unwrap().start();
+            let end = matches
+                .get(i + 1)
+                .map_or(bbl.len(), |next| next.get(0).unwrap().start());
+            entries += &bbl[start..end];
+        }
+    }
+    entries
+}
+
+/// Counts the slides of a frame from the `\pause`s and overlay specifications in its `body`.
+/// Incremental specifications like `<+->`, also as default `[<+->]` of lists, advance the
+/// pause counter like they do in beamer.
+fn count_overlays(body: &str, dead: &[Range<usize>]) -> usize {
+    // value of beamer's `beamerpauses` counter
+    let mut pauses = 1;
+    let mut overlays = 1;
+    let mut list_specs: Vec<Option<String>> = Vec::new();
+
+    for cap in OVERLAY_REGEX.captures_iter(body) {
+        if is_dead(dead, cap.get(0).unwrap().start()) {
+            continue;
+        }
+        if cap.name("pause").is_some() {
+            pauses = match cap.name("pause_to") {
+                Some(to) => to.as_str().parse().unwrap_or(pauses),
+                None => pauses + 1,
+            };
+            overlays = overlays.max(pauses);
+        } else if cap.name("list").is_some() {
+            list_specs.push(cap.name("list_spec").map(|s| s.as_str().to_string()));
+        } else if cap.name("list_end").is_some() {
+            list_specs.pop();
+        } else {
+            let spec = match cap.name("spec").or_else(|| cap.name("item_spec")) {
+                Some(spec) => spec.as_str(),
+                None => match list_specs.last() {
+                    Some(Some(spec)) => spec.as_str(),
+                    _ => continue,
+                },
+            };
+            let mut increment = false;
+            for value in OVERLAY_VALUE_REGEX.captures_iter(spec) {
+                let offset: i64 = value
+                    .name("offset")
+                    .map_or(0, |o| o.as_str().parse().unwrap_or(0));
+                let slide = match value.name("relative").map(|r| r.as_str()) {
+                    Some("+") => {
+                        increment = true;
+                        pauses as i64 + offset
+                    }
+                    Some(_) => pauses as i64 - 1 + offset,
+                    None => value["number"].parse().unwrap_or(0),
+                };
+                overlays = overlays.max(slide.max(0) as usize);
+            }
+            if increment {
+                pauses += 1;
+            }
+        }
+    }
+    overlays
+}
+
+/// Returns the byte ranges of all frames of the document in `parsed_file`.
+/// Frames are taken from the syntax tree where it parses cleanly. Inside of subtrees with
+/// ERROR nodes and for `\frame{...}` and `\againframe` macros, which the grammar does not
+/// know, the regex splitter of `find_frames` is used instead.
+pub fn extract_frames(
+    parsed_file: &ParsedFile,
+    frame_envs: &[String],
+) -> Vec<(Range<usize>, FrameSource)> {
+    let mut error_regions = Vec::new();
+    let mut frames = Vec::new();
+
+    for node in get_frames(parsed_file, frame_envs) {
+        if node.has_error() {
+            error_regions.push(node.byte_range());
+        } else {
+            frames.push((node.byte_range(), FrameSource::SyntaxTree));
+        }
+    }
+    error_regions.extend(
+        parsed_file
+            .get_nodes_of_type("ERROR".to_string())
+            .iter()
+            .map(|n| n.byte_range()),
+    );
+    let error_regions = merge_ranges(error_regions);
+
+    for range in find_frames(&parsed_file.file_content, frame_envs) {
+        let in_error_region = error_regions
+            .iter()
+            .any(|r| r.start < range.end && range.start < r.end);
+        let is_macro = is_frame_macro(&parsed_file.file_content[range.clone()]);
+        let overlaps_frame = frames
+            .iter()
+            .any(|(r, _)| r.start < range.end && range.start < r.end);
+        if (in_error_region || is_macro) && !overlaps_frame {
+            frames.push((range, FrameSource::Fallback));
+        }
+    }
+    frames.sort_by_key(|(range, _)| range.start);
+    frames
+}
+
+/// Returns whether `frame` is a `\frame` or `\againframe` macro, which the grammar does not know.
+fn is_frame_macro(frame: &str) -> bool {
+    frame.starts_with("\\frame") || frame.starts_with("\\againframe")
+}
+
+/// Sorts `ranges` and merges the overlapping ones.
+fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+pub fn get_frames<'a>(parsed_file: &'a ParsedFile, frame_envs: &[String]) -> Vec<Node<'a>> {
     let mut frames = Vec::new();
 
     let text_envs = parsed_file.get_nodes_of_type("text_env".to_string());
+    let dead = dead_regions(&parsed_file.file_content);
 
     for t in text_envs {
+        if is_dead(&dead, t.start_byte()) {
+            continue;
+        }
         let children = get_children(
             t,
-            &|n| has_begin_frame(n, parsed_file),
+            &|n| has_begin_frame(n, parsed_file, frame_envs),
             true,
             TraversalOrder::BreadthFirst,
         );
-        if children.len() == 1 {
+        // only the environment that starts with the begin of the frame is the frame itself
+        if children.len() == 1 && children[0].start_byte() == t.start_byte() {
             frames.push(t)
         }
     }
     frames
 }
 
-fn has_begin_frame(node: Node, parsed_file: &ParsedFile) -> bool {
-    node.kind() == "begin"
-        && parsed_file
-            .get_node_string(&node)
-            .to_string()
-            .contains("{frame}")
+fn has_begin_frame(node: Node, parsed_file: &ParsedFile, frame_envs: &[String]) -> bool {
+    node.kind() == "begin" && {
+        let begin = parsed_file.get_node_string(&node);
+        frame_envs
+            .iter()
+            .any(|env| begin.contains(&format!("{{{}}}", env)))
+    }
+}
+
+/// Returns the file names passed to `\input`, `\include` and `\subfile` in the document
+/// together with their byte ranges.
+pub fn get_included_files(parsed_file: &ParsedFile) -> Vec<(Range<usize>, String)> {
+    let dead = dead_regions(&parsed_file.file_content);
+    parsed_file
+        .get_captures("include")
+        .iter()
+        .filter(|node| !is_dead(&dead, node.start_byte()))
+        .filter_map(|node| {
+            let text = parsed_file.get_node_string(node);
+            let start = text.find('{')? + 1;
+            let end = start + text[start..].find('}')?;
+            let name = text[start..end].trim();
+            let start = node.start_byte() + start + text[start..end].find(name)?;
+            Some((start..start + name.len(), name.to_string()))
+        })
+        .collect()
+}
+
+/// Returns the frames of the `files` included with `\input`, given by the range of their name
+/// in the document, their name and their content. The frames are placed where the file is
+/// included. Subfiles with a document of their own are left to the document.
+pub fn included_frames(
+    files: &[(Range<usize>, String, String)],
+    frame_envs: &[String],
+) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    for (name_range, name, content) in files {
+        if document_begin(content).is_some() {
+            continue;
+        }
+        for range in find_frames(content, frame_envs) {
+            let mut frame = Frame::new(content, range.clone(), FrameSource::Included);
+            frame.range = name_range.start..name_range.start;
+            frame.included = Some((name.clone(), range));
+            frames.push(frame);
+        }
+    }
+    frames
+}
+
+/// Attaches the `\note`s of `content` that are placed between two of the sorted `frames` to the
+/// frame before them, like beamer does, so that they are compiled with that frame.
+pub fn attach_notes(content: &str, frames: &mut [Frame]) {
+    let dead = dead_regions(content);
+    for (range, text) in macro_calls(content, "note", &dead) {
+        let frame_idx = frames.partition_point(|f| f.range.start <= range.start);
+        if frame_idx == 0 || range.start < frames[frame_idx - 1].range.end {
+            // before the first frame or inside of a frame
+            continue;
+        }
+        let frame = &mut frames[frame_idx - 1];
+        frame.trailing_notes += "\n";
+        frame.trailing_notes += &content[range];
+        frame.notes.push(text);
+    }
+}
+
+/// Records in each of the `frames` of `content` the sectioning commands that are in effect
+/// where the frame starts, counting them like LaTeX does.
+pub fn assign_sections(content: &str, frames: &mut [Frame]) {
+    let mut commands = sectioning_commands(content, frames).into_iter().peekable();
+    let mut open: Vec<Section> = Vec::new();
+    let mut appendix = false;
+    for frame in frames.iter_mut() {
+        while let Some((_, command)) = commands.next_if(|(pos, _)| *pos < frame.range.start) {
+            match command {
+                Some(section) => {
+                    open.retain(|s| s.level < section.level);
+                    open.push(section);
+                }
+                None => appendix = true,
+            }
+        }
+        frame.sections = open.clone();
+        frame.appendix = appendix;
+    }
+}
+
+/// Records in each of the `frames` of `content` the template, color and font settings in the
+/// body of the document that are in effect where the frame starts, e.g. a
+/// `\usebackgroundtemplate` between two frames. Settings in a group end with the group.
+pub fn assign_templates(content: &str, frames: &mut [Frame]) {
+    let dead = dead_regions(content);
+    let body = document_start(content, &dead);
+    // (start, end, end of the enclosing group) of each setting
+    let settings: Vec<(usize, usize, usize)> = TEMPLATE_REGEX
+        .find_iter(content)
+        .filter(|m| {
+            m.start() >= body
+                && !is_dead(&dead, m.start())
+                && !frames.iter().any(|f| f.range.contains(&m.start()))
+        })
+        .filter_map(|m| {
+            let end = template_command_end(content, m.start())?;
+            Some((m.start(), end, group_end(content, end)))
+        })
+        .collect();
+    for frame in frames.iter_mut() {
+        frame.templates = settings
+            .iter()
+            .filter(|(start, _, group_end)| {
+                *start < frame.range.start && frame.range.start < *group_end
+            })
+            .map(|(start, end, _)| format!("{}\n", &content[*start..*end]))
+            .collect();
+    }
+}
+
+/// Returns the end of the template, color or font setting at `start`.
+fn template_command_end(content: &str, start: usize) -> Option<usize> {
+    let (name, mut pos) = control_word(content, start);
+    if content[pos..].starts_with('*') {
+        pos += 1;
+    }
+    let arguments = match name {
+        "addtobeamertemplate" => 3,
+        "setbeamercolor" | "setbeamerfont" => 2,
+        _ => 1,
+    };
+    for _ in 0..arguments {
+        pos = skip_group(content, skip_whitespace(content, pos), b'{', b'}')?;
+    }
+    if name == "setbeamertemplate" {
+        // either predefined options like `[frame number]` or the code of the template
+        let mut predefined = false;
+        while let Some(end) = skip_group(content, skip_whitespace(content, pos), b'[', b']') {
+            pos = end;
+            predefined = true;
+        }
+        if !predefined {
+            pos = skip_group(content, skip_whitespace(content, pos), b'{', b'}')?;
+        }
+    }
+    Some(pos)
+}
+
+/// Returns the position of the `}` that closes the group containing `pos`, or the end of
+/// `content` outside of groups.
+fn group_end(content: &str, pos: usize) -> usize {
+    let bytes = content.as_bytes();
+    let mut depth = 0;
+    let mut i = pos;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'%' => i = line_end(content, i),
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return i,
+            b'}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    content.len()
+}
+
+/// Returns the live sectioning commands in the body of `content` outside of the `frames`
+/// together with their position, numbered like LaTeX does. `\appendix` is returned as `None`.
+fn sectioning_commands(content: &str, frames: &[Frame]) -> Vec<(usize, Option<Section>)> {
+    let dead = dead_regions(content);
+    let body = document_start(content, &dead);
+    let mut counters = [0; 4];
+    let mut commands = Vec::new();
+    for m in SECTION_REGEX.find_iter(content) {
+        if m.start() < body
+            || is_dead(&dead, m.start())
+            || frames.iter().any(|f| f.range.contains(&m.start()))
+        {
+            continue;
+        }
+        if control_word(content, m.start()).0 == "appendix" {
+            commands.push((m.start(), None));
+            continue;
+        }
+        let (level, starred, title, end) = match parse_section(content, m.start()) {
+            Some(section) => section,
+            None => continue,
+        };
+
+        let number = if starred {
+            None
+        } else {
+            counters[level] += 1;
+            for counter in counters.iter_mut().skip(level + 1) {
+                *counter = 0;
+            }
+            Some(counters[level])
+        };
+        commands.push((
+            m.start(),
+            Some(Section {
+                level,
+                number,
+                text: content[m.start()..end].to_string(),
+                title,
+            }),
+        ));
+    }
+    commands
+}
+
+/// Parses the `\part`, `\section`, `\subsection` or `\subsubsection` command at `start` and
+/// returns its level, whether it is starred, its long title and the position behind it.
+fn parse_section(content: &str, start: usize) -> Option<(usize, bool, String, usize)> {
+    let (name, mut pos) = control_word(content, start);
+    let level = SECTION_LEVELS.iter().position(|l| *l == name)?;
+    let starred = content[pos..].starts_with('*');
+    if starred {
+        pos += 1;
+    }
+    loop {
+        let start = skip_whitespace(content, pos);
+        match skip_group(content, start, b'<', b'>')
+            .or_else(|| skip_group(content, start, b'[', b']'))
+        {
+            Some(end) => pos = end,
+            None => break,
+        }
+    }
+    let start = skip_whitespace(content, pos);
+    let end = skip_group(content, start, b'{', b'}')?;
+    Some((
+        level,
+        starred,
+        normalize_text(&content[start + 1..end - 1]),
+        end,
+    ))
+}
+
+/// Synthesizes the frames that beamer adds without a frame in the source of `content`: the
+/// title pages of `\maketitle` and `\titlepage` outside of the `frames` and the frames that
+/// the hooks of `\AtBeginPart`, `\AtBeginSection` and friends add at each sectioning command.
+/// The synthesized frames have an empty range behind the command that adds them, so that
+/// `assign_sections` gives hook frames the section that triggered them.
+pub fn implicit_frames(content: &str, frames: &[Frame], frame_envs: &[String]) -> Vec<Frame> {
+    let dead = dead_regions(content);
+    let body = document_start(content, &dead);
+    let outside_frames = |pos: usize| {
+        pos >= body && !is_dead(&dead, pos) && !frames.iter().any(|f| f.range.contains(&pos))
+    };
+
+    // (position, level, code for starred commands, code) of each hook definition
+    let mut hooks: Vec<(usize, usize, Option<&str>, &str)> = Vec::new();
+    let mut hook_ranges = Vec::new();
+    for m in SECTION_HOOK_REGEX.find_iter(content) {
+        if is_dead(&dead, m.start()) {
+            continue;
+        }
+        let (name, mut pos) = control_word(content, m.start());
+        let level = SECTION_LEVELS
+            .iter()
+            .position(|l| name["AtBegin".len()..].eq_ignore_ascii_case(l))
+            .unwrap();
+        let mut starred_code = None;
+        let start = skip_whitespace(content, pos);
+        if let Some(end) = skip_group(content, start, b'[', b']') {
+            starred_code = Some(&content[start + 1..end - 1]);
+            pos = end;
+        }
+        let start = skip_whitespace(content, pos);
+        if let Some(end) = skip_group(content, start, b'{', b'}') {
+            hooks.push((m.start(), level, starred_code, &content[start + 1..end - 1]));
+            hook_ranges.push(m.start()..end);
+        }
+    }
+    let outside_hooks = |pos: usize| !hook_ranges.iter().any(|r| r.contains(&pos));
+
+    let mut implicit = Vec::new();
+    for m in SECTION_REGEX.find_iter(content) {
+        if !outside_frames(m.start()) || !outside_hooks(m.start()) {
+            continue;
+        }
+        let (level, starred, _, end) = match parse_section(content, m.start()) {
+            Some(section) => section,
+            None => continue,
+        };
+        let code = hooks
+            .iter()
+            .rev()
+            .find(|(pos, hook_level, _, _)| *pos < m.start() && *hook_level == level)
+            .and_then(|(_, _, starred_code, code)| {
+                if starred {
+                    *starred_code
+                } else {
+                    Some(*code)
+                }
+            });
+        if let Some(frame) = code.and_then(|code| implicit_frame(code, end, frame_envs)) {
+            implicit.push(frame);
+        }
+    }
+    for m in TITLE_PAGE_REGEX.find_iter(content) {
+        if outside_frames(m.start()) && outside_hooks(m.start()) {
+            // beamer typesets \maketitle outside of frames as a frame of its own
+            implicit.extend(implicit_frame("\\frame{\\titlepage}", m.end(), frame_envs));
+        }
+    }
+    implicit.sort_by_key(|f| f.range.start);
+    implicit
+}
+
+/// Reads the implicit frame that `code` adds at `position` of the document. The metadata is
+/// read from the first frame in `code`, which is compiled as a whole.
+fn implicit_frame(code: &str, position: usize, frame_envs: &[String]) -> Option<Frame> {
+    let code = code.trim();
+    let first = find_frames(code, frame_envs).into_iter().next()?;
+    let mut frame = Frame::new(code, first.clone(), FrameSource::Implicit);
+    frame.range = position..position;
+    frame.text = code.to_string();
+    frame.body_start += first.start;
+    Some(frame)
+}
+
+/// Returns the `.toc` file that beamer would write for the sections of `content`, given the
+/// number of `pages` that each of the `frames` produces.
+pub fn table_of_contents(content: &str, frames: &[Frame], pages: &[usize]) -> String {
+    let mut toc = String::new();
+    let mut numbers = [0; 4];
+    for (pos, command) in sectioning_commands(content, frames) {
+        // starred commands have no entry
+        let section = match command {
+            Some(section) if section.number.is_some() => section,
+            _ => continue,
+        };
+        numbers[section.level] = section.number.unwrap();
+        for deeper in numbers.iter_mut().skip(section.level + 1) {
+            *deeper = 0;
+        }
+        let page = 1 + frames
+            .iter()
+            .zip(pages)
+            .filter(|(f, _)| f.range.start < pos)
+            .map(|(_, pages)| pages)
+            .sum::<usize>();
+        let [part, section_number, subsection, subsubsection] = numbers;
+        toc += &match section.level {
+            0 => format!("\\beamer@partintoc {{{}}}{{{}}}{{{}}}\n", part, section.title, page),
+            1 => format!(
+                "\\beamer@sectionintoc {{{}}}{{{}}}{{{}}}{{{}}}{{{}}}\n",
+                section_number, section.title, page, part, section_number
+            ),
+            2 => format!(
+                "\\beamer@subsectionintoc {{{}}}{{{}}}{{{}}}{{{}}}{{{}}}{{{}}}\n",
+                section_number, subsection, section.title, page, part, section_number
+            ),
+            _ => format!(
+                "\\beamer@subsubsectionintoc {{{}}}{{{}}}{{{}}}{{{}}}{{{}}}{{{}}}{{{}}}\n",
+                section_number,
+                subsection,
+                subsubsection,
+                section.title,
+                page,
+                part,
+                section_number
+            ),
+        };
+    }
+    toc
+}
+
+/// Returns whether the theme of `preamble` draws a navigation from the `.nav` file, like the
+/// miniframes of Berlin or the sidebar of Goettingen.
+pub fn uses_navigation(preamble: &str) -> bool {
+    let dead = dead_regions(preamble);
+    NAVIGATION_REGEX
+        .find_iter(preamble)
+        .any(|m| !is_dead(&dead, m.start()))
+}
+
+/// Returns the `.nav` file that beamer would write for the sections and `frames` of
+/// `content`, given the number of `pages` that each frame produces.
+pub fn navigation(content: &str, frames: &[Frame], pages: &[usize]) -> String {
+    // closes the page ranges of the open levels from `level` down
+    fn close(nav: &mut String, starts: &[usize; 3], level: usize, page: usize) {
+        for level in (level..3).rev() {
+            *nav += &format!(
+                "\\headcommand {{\\beamer@{}pages {{{}}}{{{}}}}}\n",
+                SECTION_LEVELS[level],
+                starts[level],
+                page - 1
+            );
+        }
+    }
+
+    let mut nav = String::new();
+    let mut commands = sectioning_commands(content, frames).into_iter().peekable();
+    // numbers and first pages of the open part, section and subsection
+    let mut numbers = [0; 3];
+    let mut starts = [1; 3];
+    let mut slide = 0;
+    let mut page = 1;
+    for (frame, frame_pages) in frames.iter().zip(pages) {
+        while let Some((_, command)) = commands.next_if(|(pos, _)| *pos < frame.range.start) {
+            // starred commands and subsubsections have no entry
+            let (section, number) = match command {
+                Some(section) if section.level < 3 => match section.number {
+                    Some(number) => (section, number),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            close(&mut nav, &starts, section.level, page);
+            numbers[section.level] = number;
+            for deeper in numbers.iter_mut().skip(section.level + 1) {
+                *deeper = 0;
+            }
+            for start in starts.iter_mut().skip(section.level) {
+                *start = page;
+            }
+            slide = 0;
+            let [part, section_number, subsection] = numbers;
+            nav += &match section.level {
+                0 => String::new(),
+                1 => format!(
+                    "\\headcommand {{\\sectionentry {{{}}}{{{}}}{{{}}}{{{}}}{{{}}}}}\n",
+                    section_number,
+                    section.title,
+                    page,
+                    section.short_title(),
+                    part
+                ),
+                _ => format!(
+                    concat!(
+                        "\\headcommand {{\\beamer@subsectionentry ",
+                        "{{{}}}{{{}}}{{{}}}{{{}}}{{{}}}}}\n"
+                    ),
+                    part,
+                    section_number,
+                    subsection,
+                    page,
+                    section.short_title()
+                ),
+            };
+        }
+        slide += 1;
+        let last = page + frame_pages.max(&1) - 1;
+        let [part, section_number, subsection] = numbers;
+        nav += &format!(
+            "\\headcommand {{\\slideentry {{{}}}{{{}}}{{{}}}{{{}/{}}}{{}}{{{}}}}}\n",
+            section_number, subsection, slide, page, last, part
+        );
+        nav += &format!("\\headcommand {{\\beamer@framepages {{{}}}{{{}}}}}\n", page, last);
+        page = last + 1;
+    }
+    close(&mut nav, &starts, 0, page);
+    let total_frames: usize = frames
+        .iter()
+        .zip(pages)
+        .map(|(f, pages)| f.frame_count(*pages))
+        .sum();
+    nav += &format!("\\headcommand {{\\beamer@documentpages {{{}}}}}\n", page - 1);
+    nav += &format!(
+        "\\headcommand {{\\gdef \\inserttotalframenumber {{{}}}}}\n",
+        total_frames
+    );
+    nav
+}
+
+/// Returns `content` with the source of each of the `frames` replaced by the corresponding
+/// one of the `replacements`. The frames are replaced by their ranges, so identical frames and
+/// frames that contain the text of others are replaced at the right place. Implicit frames
+/// have no source and frames in files included with `\input` are not part of `content`, see
+/// `replace_included_frames` for those. The names of included files at the ranges of
+/// `renamed_files` are replaced by the given names.
+pub fn replace_frames(
+    content: &str,
+    frames: &[Frame],
+    replacements: &[String],
+    renamed_files: &[(Range<usize>, String)],
+) -> String {
+    let mut ranges: Vec<(Range<usize>, &String)> = frames
+        .iter()
+        .zip(replacements)
+        .filter(|(f, _)| f.source != FrameSource::Implicit && f.included.is_none())
+        .map(|(f, replacement)| (f.range.clone(), replacement))
+        .chain(renamed_files.iter().map(|(range, name)| (range.clone(), name)))
+        .collect();
+    ranges.sort_by_key(|(range, _)| range.start);
+    replace_ranges(content, ranges.into_iter())
+}
+
+/// Returns the `content` of the file `name` included with `\input` with the source of each of
+/// its `frames` replaced by the corresponding one of the `replacements`, like `replace_frames`.
+pub fn replace_included_frames(
+    content: &str,
+    name: &str,
+    frames: &[Frame],
+    replacements: &[String],
+) -> String {
+    replace_ranges(
+        content,
+        frames
+            .iter()
+            .zip(replacements)
+            .filter_map(|(f, replacement)| match &f.included {
+                Some((file, range)) if file == name => Some((range.clone(), replacement)),
+                _ => None,
+            }),
+    )
+}
+
+/// Returns `content` with the sorted `ranges` replaced. Ranges that overlap a previous one are
+/// skipped.
+fn replace_ranges<'a>(
+    content: &str,
+    ranges: impl Iterator<Item = (Range<usize>, &'a String)>,
+) -> String {
+    let mut result = String::with_capacity(content.len());
+    let mut copied = 0;
+    for (range, replacement) in ranges {
+        if range.start < copied {
+            continue;
+        }
+        result += &content[copied..range.start];
+        result += replacement;
+        copied = range.end;
+    }
+    result + &content[copied..]
+}
+
+/// Returns the values of the `framenumber` and `page` counters before each of the `frames`,
+/// given the number of `pages` that each frame produces. With `restart_in_appendix` the frame
+/// numbers start over at `\appendix`, like with the appendixnumberbeamer package.
+pub fn frame_numbers(
+    frames: &[Frame],
+    pages: &[usize],
+    restart_in_appendix: bool,
+) -> Vec<(usize, usize)> {
+    let mut framenumber = 0;
+    let mut page = 0;
+    let mut in_appendix = false;
+    frames
+        .iter()
+        .zip(pages)
+        .map(|(frame, &pages)| {
+            if restart_in_appendix && frame.appendix && !in_appendix {
+                in_appendix = true;
+                framenumber = 0;
+            }
+            let numbers = (framenumber, page);
+            framenumber += frame.frame_count(pages);
+            page += pages;
+            numbers
+        })
+        .collect()
+}
+
+/// Returns the names of all environments that produce a frame: `frame`, the `extra_envs` given
+/// by the user and all environments defined with `\newenvironment` that wrap `frame`.
+pub fn frame_environments(content: &str, extra_envs: &[String]) -> Vec<String> {
+    let mut envs = vec!["frame".to_string()];
+    envs.extend(extra_envs.iter().cloned());
+
+    let dead = dead_regions(content);
+    for cap in NEWENVIRONMENT_REGEX.captures_iter(content) {
+        if is_dead(&dead, cap.get(0).unwrap().start()) {
+            continue;
+        }
+        let name = cap[1].trim().to_string();
+        let mut pos = skip_whitespace(content, cap.get(0).unwrap().end());
+        // skip number of arguments and default value of optional argument
+        while let Some(end) = skip_group(content, pos, b'[', b']') {
+            pos = skip_whitespace(content, end);
+        }
+        if let Some(end) = skip_group(content, pos, b'{', b'}') {
+            if content[pos..end].contains("\\begin{frame}") && !envs.contains(&name) {
+                debug!("Treating environment {} as frame", name);
+                envs.push(name);
+            }
+        }
+    }
+    envs
+}
+
+/// Returns the byte ranges of all frames in the document body of `content`.
+/// Frames are either environments in `frame_envs`, `\frame{...}` or `\againframe` macros.
+pub fn find_frames(content: &str, frame_envs: &[String]) -> Vec<Range<usize>> {
+    let envs: Vec<_> = frame_envs.iter().map(|e| regex::escape(e)).collect();
+    let frame_regex = Regex::new(&format!(
+        r"\\begin\s*\{{({})\}}|\\frame\b|\\againframe\b",
+        envs.join("|")
+    ))
+    .unwrap();
+
+    let dead = dead_regions(content);
+    let mut frames = Vec::new();
+    let mut pos = document_start(content, &dead);
+
+    while let Some(m) = frame_regex.find_at(content, pos) {
+        if is_dead(&dead, m.start()) {
+            pos = m.end();
+            continue;
+        }
+        let end = if m.as_str().starts_with("\\begin") {
+            let env = m.as_str()["\\begin".len()..]
+                .trim()
+                .trim_start_matches('{')
+                .trim_end_matches('}');
+            find_env_end(content, m.end(), env, &dead)
+        } else {
+            find_frame_macro_end(content, m.end())
+        };
+
+        match end {
+            Some(end) => {
+                frames.push(m.start()..end);
+                pos = end;
+            }
+            None => pos = m.end(),
+        }
+    }
+    frames
+}
+
+/// Returns the position of the first live `\begin{document}` of `content`.
+pub fn document_begin(content: &str) -> Option<usize> {
+    let dead = dead_regions(content);
+    content
+        .match_indices("\\begin{document}")
+        .map(|(start, _)| start)
+        .find(|start| !is_dead(&dead, *start))
+}
+
+/// Returns the position behind the first live `\begin{document}` or 0 if there is none.
+fn document_start(content: &str, dead: &[Range<usize>]) -> usize {
+    content
+        .match_indices("\\begin{document}")
+        .find(|(start, _)| !is_dead(dead, *start))
+        .map(|(start, begin)| start + begin.len())
+        .unwrap_or(0)
+}
+
+/// Returns the end of `\end{env}` matching a `\begin{env}` that ends before `pos`.
+/// Occurrences inside of `dead` regions are ignored.
+fn find_env_end(content: &str, pos: usize, env: &str, dead: &[Range<usize>]) -> Option<usize> {
+    let begin = format!("\\begin{{{}}}", env);
+    let end = format!("\\end{{{}}}", env);
+    let mut depth = 1;
+    let mut pos = pos;
+
+    while depth > 0 {
+        let next_end = pos + content[pos..].find(&end)?;
+        let (next, len, step) = match content[pos..next_end].find(&begin) {
+            Some(next_begin) => (pos + next_begin, begin.len(), 1),
+            None => (next_end, end.len(), -1),
+        };
+        if !is_dead(dead, next) {
+            depth += step;
+        }
+        pos = next + len;
+    }
+    Some(pos)
+}
+
+/// Returns whether `pos` lies in one of the sorted, disjoint `regions`.
+pub fn is_dead(regions: &[Range<usize>], pos: usize) -> bool {
+    match regions.binary_search_by(|r| r.start.cmp(&pos)) {
+        Ok(_) => true,
+        Err(0) => false,
+        Err(i) => regions[i - 1].contains(&pos),
+    }
+}
+
+/// Returns the sorted byte ranges of `content` that can never contain a live frame:
+/// comments, `\iffalse ... \fi` blocks, inline verbatim and the environments in `VERBATIM_ENVS`.
+pub fn dead_regions(content: &str) -> Vec<Range<usize>> {
+    let bytes = content.as_bytes();
+    let mut regions = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let end = line_end(content, i);
+                regions.push(i..end);
+                i = end;
+            }
+            b'\\' => {
+                let (word, word_end) = control_word(content, i);
+                let end = match word {
+                    "iffalse" => Some(find_fi(content, word_end)),
+                    "verb" | "lstinline" => find_verb_end(content, word_end),
+                    "begin" => {
+                        let name_start = skip_whitespace(content, word_end);
+                        skip_group(content, name_start, b'{', b'}').and_then(|name_end| {
+                            let name = &content[name_start + 1..name_end - 1];
+                            if VERBATIM_ENVS.contains(&name) {
+                                let end = format!("\\end{{{}}}", name);
+                                Some(content[name_end..].find(&end).map_or(content.len(), |e| {
+                                    name_end + e + end.len()
+                                }))
+                            } else {
+                                None
+                            }
+                        })
+                    }
+                    _ => None,
+                };
+                match end {
+                    Some(end) => {
+                        regions.push(i..end);
+                        i = end;
+                    }
+                    None => i = word_end.max(i + 2),
+                }
+            }
+            _ => i += 1,
+        }
+    }
+    regions
+}
+
+fn line_end(content: &str, pos: usize) -> usize {
+    content[pos..].find('\n').map_or(content.len(), |end| pos + end)
+}
+
+/// Returns the name of the control word starting at `pos` and the position behind it.
+fn control_word(content: &str, pos: usize) -> (&str, usize) {
+    let start = pos + 1;
+    let len = content[start..]
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(content.len() - start);
+    (&content[start..start + len], start + len)
+}
+
+/// Returns the end of the `\iffalse` block whose content starts at `pos`.
+/// The block ends with the matching `\fi` or `\else`.
+fn find_fi(content: &str, pos: usize) -> usize {
+    let bytes = content.as_bytes();
+    let mut depth = 1;
+    let mut i = pos;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => i = line_end(content, i),
+            b'\\' => {
+                let (word, word_end) = control_word(content, i);
+                match word {
+                    "fi" => depth -= 1,
+                    "else" if depth == 1 => depth -= 1,
+                    w if w.starts_with("if") && w != "ifthenelse" => depth += 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    return word_end;
+                }
+                i = word_end.max(i + 2);
+            }
+            _ => i += 1,
+        }
+    }
+    content.len()
+}
+
+/// Returns the end of `\verb|text|` where `pos` points behind `\verb`.
+fn find_verb_end(content: &str, pos: usize) -> Option<usize> {
+    let pos = if content[pos..].starts_with('*') { pos + 1 } else { pos };
+    let delimiter = content[pos..].chars().next()?;
+    if delimiter == '{' || delimiter.is_whitespace() || delimiter.is_ascii_alphabetic() {
+        return None;
+    }
+    let start = pos + delimiter.len_utf8();
+    content[start..]
+        .find(delimiter)
+        .map(|end| start + end + delimiter.len_utf8())
+}
+
+/// Returns the end of `\frame<overlay>[options]{text}` or `\againframe<overlay>[options]{label}`
+/// where `pos` points behind the command.
+fn find_frame_macro_end(content: &str, pos: usize) -> Option<usize> {
+    let mut pos = skip_whitespace(content, pos);
+    while let Some(end) =
+        skip_group(content, pos, b'<', b'>').or_else(|| skip_group(content, pos, b'[', b']'))
+    {
+        pos = skip_whitespace(content, end);
+    }
+    skip_group(content, pos, b'{', b'}')
+}
+
+fn skip_whitespace(content: &str, pos: usize) -> usize {
+    pos + content[pos..].len() - content[pos..].trim_start().len()
+}
+
+/// If a group delimited by `open` and `close` starts at `pos`, returns the position behind it.
+fn skip_group(content: &str, pos: usize, open: u8, close: u8) -> Option<usize> {
+    let bytes = content.as_bytes();
+    if bytes.get(pos) != Some(&open) {
+        return None;
+    }
+    let mut depth = 0;
+    let mut i = pos;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'%' => i = line_end(content, i),
+            c if c == open => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    fn frames_of(content: &str) -> Vec<Frame> {
+        find_frames(content, &frame_environments(content, &[]))
+            .into_iter()
+            .map(|range| Frame::new(content, range, FrameSource::Fallback))
+            .collect()
+    }
+
+    fn frame_texts<'a>(content: &'a str, envs: &[String]) -> Vec<&'a str> {
+        find_frames(content, envs)
+            .into_iter()
+            .map(|r| &content[r])
+            .collect()
+    }
 
     #[test]
     fn it_works() {}
+
+    #[test]
+    fn test_find_frame_environments() {
+        let content = r"\documentclass{beamer}
+\begin{document}
+\begin{frame}{First}
+  a
+\end{frame}
+Some text \begin{frame}[fragile]
+  b
+\end{frame}
+\end{document}";
+        let frames = frame_texts(content, &frame_environments(content, &[]));
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].starts_with(r"\begin{frame}{First}"));
+        assert!(frames[1].starts_with(r"\begin{frame}[fragile]"));
+        assert!(frames[1].ends_with(r"\end{frame}"));
+    }
+
+    #[test]
+    fn test_find_frame_macros() {
+        let content = r"\begin{document}
+\frame<2>[plain]{\frametitle{Macro} {nested} \}}
+\frame{\titlepage}
+\end{document}";
+        let frames = frame_texts(content, &frame_environments(content, &[]));
+        assert_eq!(
+            frames,
+            vec![
+                r"\frame<2>[plain]{\frametitle{Macro} {nested} \}}",
+                r"\frame{\titlepage}"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_custom_frame_environments() {
+        let content = r"\documentclass{beamer}
+\newenvironment{myframe}[1]{\begin{frame}{#1}}{\end{frame}}
+\newenvironment{notaframe}{\begin{center}}{\end{center}}
+% \newenvironment{oldframe}{\begin{frame}}{\end{frame}}
+\begin{document}
+\begin{myframe}{Title}
+  a
+\end{myframe}
+\begin{extraframe}
+  b
+\end{extraframe}
+\end{document}";
+        let envs = frame_environments(content, &["extraframe".to_string()]);
+        assert_eq!(envs, vec!["frame", "extraframe", "myframe"]);
+        let frames = frame_texts(content, &envs);
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].starts_with(r"\begin{myframe}"));
+        assert!(frames[1].starts_with(r"\begin{extraframe}"));
+    }
+
+    #[test]
+    fn test_skip_dead_frames() {
+        let content = r"\begin{document}
+% \begin{frame}
+%   commented
+% \end{frame}
+\iffalse
+\begin{frame}{Disabled} \ifx a b \fi \end{frame}
+\fi
+\begin{comment}
+\begin{frame}\end{frame}
+\end{comment}
+\begin{frame}[fragile]{Live}
+\begin{verbatim}
+\begin{frame}
+\end{frame}
+\end{verbatim}
+\verb|\end{frame}| 50\% % \end{frame}
+\end{frame}
+\iffalse \frame{a} \else \frame{b} \fi
+\begin{lstlisting}
+\frame{c}
+\end{lstlisting}
+\end{document}";
+        let frames = frame_texts(content, &frame_environments(content, &[]));
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].starts_with(r"\begin{frame}[fragile]{Live}"));
+        assert!(frames[0].ends_with("% \\end{frame}\n\\end{frame}"));
+        assert_eq!(frames[1], r"\frame{b}");
+    }
+
+    #[test]
+    fn test_frame_metadata() {
+        let content = r"\begin{document}
+\begin{frame}<1-2>[<+->][fragile, label=intro,t]
+  {The   Title}
+  {Sub}
+  a
+\end{frame}
+\begin{frame}[allowframebreaks]
+  % \frametitle{Commented}
+  \frametitle<2>[short]{Body {Title}}
+  \framesubtitle{Body Subtitle}
+\end{frame}
+\frame[plain,label={macro}]{\frametitle{Macro}}
+\begin{frame}
+
+  {not a title}
+\end{frame}
+\end{document}";
+        let frames = frames_of(content);
+        assert_eq!(frames.len(), 4);
+
+        assert_eq!(frames[0].title.as_deref(), Some("The Title"));
+        assert_eq!(frames[0].subtitle.as_deref(), Some("Sub"));
+        assert_eq!(
+            frames[0].options,
+            FrameOptions {
+                fragile: true,
+                label: Some("intro".to_string()),
+                alignment: Some('t'),
+                ..FrameOptions::default()
+            }
+        );
+        assert_eq!(frames[0].describe(0), "frame 1 \"The Title\"");
+
+        assert!(frames[1].options.allowframebreaks);
+        assert_eq!(frames[1].title.as_deref(), Some("Body {Title}"));
+        assert_eq!(frames[1].subtitle.as_deref(), Some("Body Subtitle"));
+
+        assert!(frames[2].options.plain);
+        assert_eq!(frames[2].options.label.as_deref(), Some("macro"));
+        assert_eq!(frames[2].title.as_deref(), Some("Macro"));
+        assert_eq!(frames[2].text, r"\frame[plain,label={macro}]{\frametitle{Macro}}");
+
+        assert_eq!(frames[3].title, None);
+        assert_eq!(frames[3].describe(3), "frame 4");
+    }
+
+    #[test]
+    fn test_assign_sections() {
+        let content = r"\section{Preamble}
+\begin{document}
+\begin{frame}\titlepage\end{frame}
+\part{One}
+\section[Intro]{Introduction}
+% \section{Commented}
+\begin{frame}{A}\end{frame}
+\subsection{Details}
+\begin{frame}{B}\section{Inside}\end{frame}
+\section*{Unnumbered}
+\begin{frame}{C}\end{frame}
+\section{Second}
+\begin{frame}{D}\end{frame}
+\part{Two}
+\section{Restart}
+\begin{frame}{E}\end{frame}
+\end{document}";
+        let mut frames = frames_of(content);
+        assign_sections(content, &mut frames);
+        let sections = |frame: &Frame| -> Vec<(usize, Option<usize>, String)> {
+            frame
+                .sections
+                .iter()
+                .map(|s| (s.level, s.number, s.text.clone()))
+                .collect()
+        };
+
+        assert_eq!(frames.len(), 6);
+        assert!(frames[0].sections.is_empty());
+        assert_eq!(frames[0].section_replay(), "");
+        assert_eq!(
+            sections(&frames[1]),
+            vec![
+                (0, Some(1), r"\part{One}".to_string()),
+                (1, Some(1), r"\section[Intro]{Introduction}".to_string())
+            ]
+        );
+        assert_eq!(
+            sections(&frames[2])[2],
+            (2, Some(1), r"\subsection{Details}".to_string())
+        );
+        assert_eq!(
+            sections(&frames[3])[1..],
+            [(1, None, r"\section*{Unnumbered}".to_string())]
+        );
+        assert_eq!(
+            sections(&frames[4])[1],
+            (1, Some(2), r"\section{Second}".to_string())
+        );
+        assert_eq!(
+            sections(&frames[5]),
+            vec![
+                (0, Some(2), r"\part{Two}".to_string()),
+                (1, Some(1), r"\section{Restart}".to_string())
+            ]
+        );
+        assert!(frames[4].section_replay().ends_with(concat!(
+            "\\setcounter{part}{0}\\part{One}\n",
+            "\\setcounter{section}{1}\\section{Second}\n"
+        )));
+    }
+
+    #[test]
+    fn test_implicit_frames() {
+        let content = r"\documentclass{beamer}
+\AtBeginSection[\begin{frame}{Unnumbered}\end{frame}]{
+  \begin{frame}<beamer>{Outline}\tableofcontents[currentsection]\end{frame}
+}
+\begin{document}
+\maketitle
+\begin{frame}\titlepage\end{frame}
+\section{Introduction}
+\begin{frame}{A}\end{frame}
+\subsection{Details}
+\section*{Starred}
+\begin{frame}{B}\section{Inside}\end{frame}
+\AtBeginSection{}
+\section{Last}
+\begin{frame}{C}\tableofcontents\end{frame}
+\end{document}";
+        let frame_envs = frame_environments(content, &[]);
+        let mut frames = frames_of(content);
+        let implicit = implicit_frames(content, &frames, &frame_envs);
+
+        assert_eq!(implicit.len(), 3);
+        assert_eq!(implicit[0].text, r"\frame{\titlepage}");
+        assert!(implicit[0].range.is_empty());
+        assert_eq!(implicit[0].range.start, content.find("\\maketitle").unwrap() + 10);
+        assert_eq!(implicit[1].title.as_deref(), Some("Outline"));
+        assert!(implicit[1].text.ends_with(r"\tableofcontents[currentsection]\end{frame}"));
+        assert!(implicit[1].references.table_of_contents);
+        assert_eq!(implicit[2].title.as_deref(), Some("Unnumbered"));
+        assert!(implicit.iter().all(|f| f.source == FrameSource::Implicit));
+
+        frames.extend(implicit);
+        frames.sort_by_key(|f| (f.range.start, f.range.end));
+        assign_sections(content, &mut frames);
+        let titles: Vec<_> = frames.iter().map(|f| f.title.as_deref().unwrap_or("-")).collect();
+        assert_eq!(titles, vec!["-", "-", "Outline", "A", "Unnumbered", "B", "C"]);
+        assert_eq!(frames[2].sections[0].title, "Introduction");
+        // the hook is cleared, so the frame does not add the outline a second time
+        assert!(frames[2]
+            .section_replay()
+            .starts_with(r"\AtBeginPart{}\AtBeginSection[]{}"));
+
+        let toc = table_of_contents(content, &frames, &[1, 1, 1, 2, 1, 1, 1]);
+        assert_eq!(
+            toc,
+            concat!(
+                "\\beamer@sectionintoc {1}{Introduction}{3}{0}{1}\n",
+                "\\beamer@subsectionintoc {1}{1}{Details}{6}{0}{1}\n",
+                "\\beamer@sectionintoc {2}{Last}{8}{0}{2}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_assign_templates() {
+        let content = r"\documentclass{beamer}
+\usebackgroundtemplate{\color{red}}
+\begin{document}
+\begin{frame}A\end{frame}
+\setbeamertemplate{footline}[frame number]
+\setbeamercolor*{normal text}{fg=blue}
+\begin{frame}B\setbeamertemplate{footline}{}\end{frame}
+{
+  \usebackgroundtemplate{\includegraphics{bg}}
+  \begin{frame}C\end{frame}
+}
+% \setbeamertemplate{headline}{}
+\begin{frame}D\end{frame}
+\end{document}";
+        let mut frames = frames_of(content);
+        assign_templates(content, &mut frames);
+        let settings = concat!(
+            "\\setbeamertemplate{footline}[frame number]\n",
+            "\\setbeamercolor*{normal text}{fg=blue}\n"
+        );
+        assert_eq!(frames[0].templates, "");
+        assert_eq!(frames[1].templates, settings);
+        assert_eq!(
+            frames[2].templates,
+            settings.to_string() + "\\usebackgroundtemplate{\\includegraphics{bg}}\n"
+        );
+        assert_eq!(frames[3].templates, settings);
+    }
+
+    #[test]
+    fn test_navigation() {
+        let content = r"\documentclass{beamer}
+\usetheme{Berlin}
+\begin{document}
+\begin{frame}\titlepage\end{frame}
+\section[Intro]{Introduction}
+\begin{frame}{A}a\pause b\end{frame}
+\subsection{Details}
+\begin{frame}{B}\end{frame}
+\section*{Starred}
+\section{Last}
+\begin{frame}{C}\end{frame}
+\end{document}";
+        assert!(uses_navigation(&content[..document_begin(content).unwrap()]));
+        assert!(uses_navigation(r"\useoutertheme[subsection=false]{miniframes}"));
+        assert!(!uses_navigation("\\usetheme{Madrid}\n% \\usetheme{Berlin}"));
+
+        let frames = frames_of(content);
+        assert_eq!(
+            navigation(content, &frames, &[1, 2, 1, 1]),
+            concat!(
+                "\\headcommand {\\slideentry {0}{0}{1}{1/1}{}{0}}\n",
+                "\\headcommand {\\beamer@framepages {1}{1}}\n",
+                "\\headcommand {\\beamer@subsectionpages {1}{1}}\n",
+                "\\headcommand {\\beamer@sectionpages {1}{1}}\n",
+                "\\headcommand {\\sectionentry {1}{Introduction}{2}{Intro}{0}}\n",
+                "\\headcommand {\\slideentry {1}{0}{1}{2/3}{}{0}}\n",
+                "\\headcommand {\\beamer@framepages {2}{3}}\n",
+                "\\headcommand {\\beamer@subsectionpages {2}{3}}\n",
+                "\\headcommand {\\beamer@subsectionentry {0}{1}{1}{4}{Details}}\n",
+                "\\headcommand {\\slideentry {1}{1}{1}{4/4}{}{0}}\n",
+                "\\headcommand {\\beamer@framepages {4}{4}}\n",
+                "\\headcommand {\\beamer@subsectionpages {4}{4}}\n",
+                "\\headcommand {\\beamer@sectionpages {2}{4}}\n",
+                "\\headcommand {\\sectionentry {2}{Last}{5}{Last}{0}}\n",
+                "\\headcommand {\\slideentry {2}{0}{1}{5/5}{}{0}}\n",
+                "\\headcommand {\\beamer@framepages {5}{5}}\n",
+                "\\headcommand {\\beamer@subsectionpages {5}{5}}\n",
+                "\\headcommand {\\beamer@sectionpages {5}{5}}\n",
+                "\\headcommand {\\beamer@partpages {1}{5}}\n",
+                "\\headcommand {\\beamer@documentpages {5}}\n",
+                "\\headcommand {\\gdef \\inserttotalframenumber {4}}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn test_frame_numbers() {
+        let content = r"\begin{document}
+\begin{frame}a\pause b\end{frame}
+\begin{frame}[allowframebreaks]c\end{frame}
+\begin{frame}[noframenumbering]d\end{frame}
+\appendix
+\begin{frame}e\end{frame}
+\begin{frame}f\end{frame}
+\end{document}";
+        let mut frames = frames_of(content);
+        assign_sections(content, &mut frames);
+        let pages: Vec<_> = frames.iter().map(|f| f.overlays).collect();
+        assert_eq!(pages, vec![2, 1, 1, 1, 1]);
+        assert!(frames[3].appendix && frames[3].section_replay().contains("\\appendix"));
+
+        let pages = vec![2, 3, 1, 1, 1];
+        assert_eq!(
+            frame_numbers(&frames, &pages, false),
+            vec![(0, 0), (1, 2), (4, 5), (4, 6), (5, 7)]
+        );
+        assert_eq!(
+            frame_numbers(&frames, &pages, true),
+            vec![(0, 0), (1, 2), (4, 5), (0, 6), (1, 7)]
+        );
+    }
+
+    #[test]
+    fn test_find_references() {
+        let content = r"See \ref{fig:a}, \eqref{eq}, \href{http://x}{y} % \ref{dead}
+\cite[p.~3]{knuth, lamport} \parencite[see][]{knuth} \Textcite{turing}
+\inserttotalframenumber";
+        let references = find_references(content, &dead_regions(content));
+        assert_eq!(references.labels, vec!["fig:a", "eq"]);
+        assert_eq!(references.citations, vec!["knuth", "lamport", "turing"]);
+        assert!(references.total_frames && !references.bibliography);
+
+        let content = r"\frametitle{Refs}\printbibliography";
+        let references = find_references(content, &dead_regions(content));
+        assert!(references.bibliography);
+        assert!(references.labels.is_empty() && references.citations.is_empty());
+    }
+
+    #[test]
+    fn test_bibliography_files() {
+        let content = r"\usepackage{natbib}
+\addbibresource[location=local]{extra.bib}
+\begin{document}
+\bibliographystyle{plainnat}
+% \bibliography{old}
+\bibliography{refs, more.bib}
+\end{document}";
+        assert_eq!(
+            bibliography_files(content),
+            vec!["extra.bib", "refs.bib", "more.bib"]
+        );
+        assert_eq!(
+            bibliography_commands(content)
+                .iter()
+                .map(|(command, _, _)| *command)
+                .collect::<Vec<_>>(),
+            vec!["addbibresource", "bibliographystyle", "bibliography"]
+        );
+    }
+
+    #[test]
+    fn test_bbl_entries() {
+        let bbl = r"\begin{thebibliography}{2}
+\bibitem[{Knuth(1984)}]{knuth}
+Donald Knuth.
+\bibitem{lamport}
+Leslie Lamport.
+\end{thebibliography}
+";
+        let keys = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            bbl_entries(bbl, &keys(&["knuth"])),
+            "\\bibitem[{Knuth(1984)}]{knuth}\nDonald Knuth.\n"
+        );
+        assert!(bbl_entries(bbl, &keys(&["lamport"])).starts_with("\\bibitem{lamport}"));
+
+        let bbl = r"\entry{knuth}{book}{}
+  \field{title}{TeX}
+\endentry
+\entry{lamport}{book}{}
+\endentry
+";
+        assert_eq!(
+            bbl_entries(bbl, &keys(&["knuth", "other"])),
+            "\\entry{knuth}{book}{}\n  \\field{title}{TeX}\n\\endentry\n"
+        );
+    }
+
+    #[test]
+    fn test_bibliography_tool() {
+        assert_eq!(bibliography_tool(r"\usepackage{biblatex}"), Some("biber"));
+        assert_eq!(
+            bibliography_tool(r"\usepackage[style=alpha, backend = bibtex]{biblatex}"),
+            Some("bibtex")
+        );
+        assert_eq!(
+            bibliography_tool(r"% \usepackage{biblatex}
+\bibliography{refs}"),
+            Some("bibtex")
+        );
+        assert_eq!(bibliography_tool(r"\usepackage{amsmath}"), None);
+    }
+
+    #[test]
+    fn test_overlay_restriction() {
+        let content = r"\begin{document}
+\begin{frame}<1>{Fixed}a\pause b\end{frame}
+\begin{frame}[<+->]{Steps}
+  a \pause b \pause c \only<5>{d}
+\end{frame}
+\frame{\frametitle{Macro} x}
+\end{document}";
+        let frames = frames_of(content);
+        assert_eq!(frames[0].restrict_to_overlay(2), None);
+        assert_eq!(
+            frames[2].restrict_to_overlay(1).unwrap(),
+            r"\frame<1>{\frametitle{Macro} x}"
+        );
+        let steps = &frames[1];
+        assert!(steps
+            .restrict_to_overlay(3)
+            .unwrap()
+            .starts_with(r"\begin{frame}<3>[<+->]{Steps}"));
+        assert_eq!(steps.overlays, 5);
+        assert_eq!(steps.overlay_at(0), 1);
+        assert_eq!(steps.overlay_at(steps.text.find(" b").unwrap()), 2);
+        assert_eq!(steps.overlay_at(steps.text.find(" c").unwrap()), 3);
+        assert_eq!(steps.overlay_at(steps.text.find("{d}").unwrap()), 5);
+        assert_eq!(steps.overlay_at(steps.text.len()), 5);
+    }
+
+    #[test]
+    fn test_againframe() {
+        let content = r"\includeonlyframes{intro, outro}
+\begin{document}
+\begin{frame}<1-2>[label=intro]{Intro}
+  a \pause b \pause c
+\end{frame}
+\begin{frame}{Other} \end{frame}
+\againframe<3>{intro}
+\againframe[plain]{intro}
+\againframe{missing}
+\end{document}";
+        let mut frames = frames_of(content);
+        assert_eq!(frames.len(), 5);
+        assert_eq!(frames[2].text, r"\againframe<3>{intro}");
+        resolve_againframes(&mut frames);
+
+        assert_eq!(frames[0].compile_text(), frames[0].text);
+        assert!(frames[2]
+            .compile_text()
+            .starts_with(r"\begin{frame}<3>[label=intro]{Intro}"));
+        assert_eq!(frames[2].title.as_deref(), Some("Intro"));
+        // only the third slide is shown again
+        assert_eq!(frames[2].overlays, 1);
+        assert_eq!(frames[2].restrict_to_overlay(1), None);
+        assert!(frames[3]
+            .compile_text()
+            .starts_with(r"\begin{frame}<1-2>[label=intro]"));
+        assert_eq!(frames[3].overlays, frames[0].overlays);
+        assert_eq!(frames[4].compile_text(), r"\againframe{missing}");
+
+        assert_eq!(
+            frames.iter().map(|f| f.label()).collect::<Vec<_>>(),
+            vec![Some("intro"), None, Some("intro"), Some("intro"), Some("missing")]
+        );
+        assert_eq!(
+            include_only_frames(content),
+            Some(vec!["intro".to_string(), "outro".to_string()])
+        );
+        assert_eq!(include_only_frames(r"% \includeonlyframes{a}"), None);
+    }
+
+    #[test]
+    fn test_is_changed_by() {
+        let content = r"\begin{frame}A\end{frame}
+\begin{frame}B\end{frame}";
+        let frames = frames_of(content);
+        let end = frames[0].range.end;
+        // typing right behind the first frame
+        assert!(!frames[0].is_changed_by(&(end..end + 1)));
+        assert!(!frames[1].is_changed_by(&(end..end + 1)));
+        assert!(frames[0].is_changed_by(&(end - 1..end)));
+        assert!(frames[0].is_changed_by(&(5..5)));
+        assert!(!frames[0].is_changed_by(&(end..end)));
+        assert!(!frames[1].is_changed_by(&(end + 1..end + 1)));
+    }
+
+    #[test]
+    fn test_count_spec_slides() {
+        assert_eq!(count_spec_slides("3", 3), Some(1));
+        assert_eq!(count_spec_slides("1-2,4", 4), Some(3));
+        assert_eq!(count_spec_slides("2-", 5), Some(4));
+        assert_eq!(count_spec_slides("-2,beamer:4", 4), Some(3));
+        assert_eq!(count_spec_slides("+-", 3), None);
+    }
+
+    #[test]
+    fn test_notes() {
+        let content = r"\begin{frame}{A}
+\note{First  note}
+% \note{Commented}
+\note<2>[item]{Second}
+\end{frame}";
+        let frame = Frame::new(content, 0..content.len(), FrameSource::Fallback);
+        assert_eq!(frame.notes, vec!["First note", "Second"]);
+
+        let preamble = r"\documentclass{beamer}
+\setbeameroption{show notes on second screen=right}
+% \setbeameroption{show notes}
+\setbeameroption{hide covered}";
+        assert_eq!(
+            set_notes_option(preamble, "hide notes"),
+            r"\documentclass{beamer}
+
+% \setbeameroption{show notes}
+\setbeameroption{hide covered}
+\setbeameroption{hide notes}
+"
+        );
+    }
+
+    #[test]
+    fn test_attach_notes() {
+        let content = r"\begin{document}
+\note{Before}
+\begin{frame}{A}\note{Inside}\end{frame}
+\note[item]{After A}
+% \note{Commented}
+\note{Also after A}
+\begin{frame}{B}\end{frame}
+\end{document}";
+        let mut frames = frames_of(content);
+        attach_notes(content, &mut frames);
+
+        assert_eq!(frames[0].notes, vec!["Inside", "After A", "Also after A"]);
+        assert_eq!(
+            frames[0].compile_text(),
+            r"\begin{frame}{A}\note{Inside}\end{frame}
+\note[item]{After A}
+\note{Also after A}"
+        );
+        assert!(frames[1].notes.is_empty());
+        assert_eq!(frames[1].compile_text(), frames[1].text);
+    }
+
+    #[test]
+    fn test_document_mode() {
+        let preamble = r"% \documentclass{article}
+\documentclass[handout, aspectratio=169]{beamer}
+\usetheme{Madrid}";
+        assert_eq!(
+            set_document_mode(preamble, "presentation"),
+            concat!(
+                "% \\documentclass{article}\n",
+                "\\documentclass[aspectratio=169]{beamer}\n\\usetheme{Madrid}"
+            )
+        );
+        assert!(set_document_mode(preamble, "trans")
+            .contains(r"\documentclass[aspectratio=169,trans]{beamer}"));
+        assert!(set_document_mode(preamble, "article").contains(concat!(
+            "\\documentclass[aspectratio=169]{article}\n",
+            "\\usepackage{beamerarticle}\n\\usetheme"
+        )));
+        let article = r"\documentclass{article}";
+        assert_eq!(set_document_mode(article, "handout"), article);
+    }
+
+    #[test]
+    fn test_reinsertion() {
+        let content = r"\begin{frame}[allowframebreaks]\end{frame}";
+        let frame = Frame::new(content, 0..content.len(), FrameSource::Fallback);
+        let reinsertion = frame.reinsertion("abc.pdf", 3);
+
+        assert!(reinsertion.contains(r"\setbeamertemplate{footline}{}"));
+        assert!(reinsertion.contains("\\includepdf[pages=-,noautoscale,fitpaper]{abc.pdf}\n}"));
+        assert!(reinsertion.contains(r"{\numexpr\fasterbeamerframenumber+3\relax}"));
+        assert!(reinsertion.ends_with(r"\setcounter{page}{\numexpr\fasterbeamerpage+3\relax}"));
+
+        let content = r"\begin{frame}[noframenumbering]\end{frame}";
+        let frame = Frame::new(content, 0..content.len(), FrameSource::Fallback);
+        assert!(frame
+            .reinsertion("abc.pdf", 2)
+            .contains(r"{\numexpr\fasterbeamerframenumber+0\relax}"));
+    }
+
+    #[test]
+    fn test_replace_frames() {
+        let content = r"\begin{document}
+\begin{frame}A\end{frame}
+\begin{frame}A\end{frame}
+\section{S}
+\begin{frame}[label=a]A\end{frame}
+\againframe{a}
+\end{document}";
+        let mut frames = frames_of(content);
+        let mut implicit = Frame::new(r"\frame{B}", 0..9, FrameSource::Implicit);
+        implicit.range = 80..80;
+        frames.insert(2, implicit);
+        let replacements: Vec<String> = (1..=frames.len()).map(|i| format!("<{}>", i)).collect();
+
+        assert_eq!(
+            replace_frames(content, &frames, &replacements, &[]),
+            "\\begin{document}\n<1>\n<2>\n\\section{S}\n<4>\n<5>\n\\end{document}"
+        );
+    }
+
+    #[test]
+    fn test_included_frames() {
+        let content =
+            "\\begin{document}\n\\begin{frame}A\\end{frame}\n\\input{part}\n\\end{document}";
+        let part = concat!(
+            "\\section{P}\n",
+            "\\begin{frame}B\\end{frame}\n",
+            "%\\begin{frame}C\\end{frame}\n",
+            "\\begin{frame}B\\end{frame}\n"
+        );
+        let start = content.find("part").unwrap();
+        let name = start..start + 4;
+        let frame_envs = frame_environments(content, &[]);
+        let included = included_frames(
+            &[(name.clone(), "part".to_string(), part.to_string())],
+            &frame_envs,
+        );
+        assert_eq!(included.len(), 2);
+        assert!(included.iter().all(|f| f.source == FrameSource::Included));
+        assert!(included.iter().all(|f| f.range == (start..start)));
+        assert_eq!(included[1].text, "\\begin{frame}B\\end{frame}");
+        assert_eq!(included[1].included, Some(("part".to_string(), 65..90)));
+
+        // subfiles are documents of their own
+        let subfile = concat!(
+            "\\documentclass[main]{subfiles}\n",
+            "\\begin{document}\n\\begin{frame}\\end{frame}\n\\end{document}"
+        );
+        assert!(included_frames(
+            &[(name.clone(), "sub".to_string(), subfile.to_string())],
+            &frame_envs
+        )
+        .is_empty());
+
+        let mut frames = frames_of(content);
+        frames.extend(included);
+        frames.sort_by_key(|f| (f.range.start, f.range.end));
+        let replacements: Vec<String> = (1..=frames.len()).map(|i| format!("<{}>", i)).collect();
+        assert_eq!(
+            replace_frames(
+                content,
+                &frames,
+                &replacements,
+                &[(name, "copy".to_string())]
+            ),
+            "\\begin{document}\n<1>\n\\input{copy}\n\\end{document}"
+        );
+        assert_eq!(
+            replace_included_frames(part, "part", &frames, &replacements),
+            "\\section{P}\n<2>\n%\\begin{frame}C\\end{frame}\n<3>\n"
+        );
+        assert_eq!(replace_included_frames(part, "other", &frames, &replacements), part);
+    }
+
+    #[test]
+    fn test_count_overlays() {
+        let overlays = |body: &str| count_overlays(body, &dead_regions(body));
+        assert_eq!(overlays("no overlays"), 1);
+        assert_eq!(overlays(r"a \pause b \pause c % \pause"), 3);
+        assert_eq!(overlays(r"\only<2>{a} \uncover<3-4>{b} \alert<2,5>{c}"), 5);
+        assert_eq!(overlays(r"\item<+-> a \item<+-> b \item<.-> c"), 2);
+        assert_eq!(overlays(r"\pause b \onslide<+->{c}"), 2);
+        assert_eq!(overlays(r"\begin{block}<3->{Title} a \end{block}"), 3);
+        assert_eq!(
+            overlays(
+                r"\begin{itemize}[<+->]
+  \item a
+  \item b
+  \begin{enumerate}
+    \item not incremental
+  \end{enumerate}
+  \item<1> c
+  \item d
+\end{itemize}
+\item e"
+            ),
+            3
+        );
+        assert_eq!(overlays(r"$a<b$ and $c > d$"), 1);
+    }
+
+    #[test]
+    fn test_syntax_tree_and_regex_agree_on_corpus() {
+        let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+        for entry in fs::read_dir(corpus).unwrap() {
+            let path = entry.unwrap().path();
+            let content = fs::read_to_string(&path).unwrap();
+            let parsed = ParsedFile::from_string(path.to_string_lossy().to_string(), content);
+            let envs = frame_environments(&parsed.file_content, &[]);
+
+            let regex_frames = find_frames(&parsed.file_content, &envs);
+            let frames = extract_frames(&parsed, &envs);
+            assert!(!regex_frames.is_empty(), "{:?}", path);
+            assert_eq!(
+                frames.iter().map(|(r, _)| r.clone()).collect::<Vec<_>>(),
+                regex_frames,
+                "{:?}",
+                path
+            );
+            for (range, source) in frames {
+                let is_macro = is_frame_macro(&parsed.file_content[range]);
+                assert_eq!(is_macro, source == FrameSource::Fallback, "{:?}", path);
+            }
+        }
+    }
 }
@@ -0,0 +1,25 @@
+; Queries used by faster-beamer (see `ParsedFile::get_captures`).
+;
+; The LaTeX grammar has no dedicated nodes for most commands. Commands are therefore
+; captured as the innermost node whose text starts with the command and its argument.
+
+((text_env (begin) @frame.begin) @frame
+ (#match? @frame.begin "^\\\\begin\\s*\\{frame\\}"))
+
+((_) @frame.title
+ (#match? @frame.title "^\\\\frametitle\\s*[<\\[{]"))
+
+((_) @frame.subtitle
+ (#match? @frame.subtitle "^\\\\framesubtitle\\s*[<\\[{]"))
+
+((_) @section
+ (#match? @section "^\\\\(part|section|subsection|subsubsection)\\*?\\s*[\\[{]"))
+
+((_) @include
+ (#match? @include "^\\\\(input|include|subfile)\\s*\\{"))
+
+((_) @graphics
+ (#match? @graphics "^\\\\includegraphics\\s*[\\[{]"))
+
+((_) @citation
+ (#match? @citation "^\\\\[a-zA-Z]*cite[a-zA-Z]*\\*?\\s*[\\[{]"))
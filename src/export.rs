@@ -0,0 +1,186 @@
+//
+// export.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::beamer::Frame;
+use regex::Regex;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref CONTROL_WORD_REGEX: Regex = Regex::new(r"\\[a-zA-Z]+\*?").unwrap();
+}
+
+/// Returns the name of the exported PDF of frame `frame_idx` of `frame_count` frames, like
+/// `003-introduction.pdf` from the title or label of the frame.
+pub fn export_name(frame: &Frame, frame_idx: usize, frame_count: usize) -> String {
+    let width = frame_count.to_string().len().max(3);
+    let slug = frame
+        .title
+        .as_deref()
+        .or_else(|| frame.label())
+        .map(slugify)
+        .unwrap_or_default();
+    if slug.is_empty() {
+        format!("{:0width$}.pdf", frame_idx + 1, width = width)
+    } else {
+        format!("{:0width$}-{}.pdf", frame_idx + 1, slug, width = width)
+    }
+}
+
+/// Turns `text` into lowercase words separated by `-`, dropping LaTeX commands. Common Latin
+/// letters with diacritics are transliterated, other letters and digits are kept.
+fn slugify(text: &str) -> String {
+    let text = CONTROL_WORD_REGEX.replace_all(text, " ");
+    let mut transliterated = String::new();
+    for c in text.chars().flat_map(char::to_lowercase) {
+        match transliterate(c) {
+            Some(latin) => transliterated += latin,
+            None => transliterated.push(c),
+        }
+    }
+    let mut slug = String::new();
+    for word in transliterated
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+    {
+        if slug.chars().count() + word.chars().count() > 40 {
+            break;
+        }
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug += word;
+    }
+    slug
+}
+
+/// Returns the ASCII spelling of the lowercase Latin letter `c` with a diacritic.
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        'ä' | 'æ' => "ae",
+        'ö' | 'ø' | 'œ' => "oe",
+        'ü' => "ue",
+        'ß' => "ss",
+        'à' | 'á' | 'â' | 'ã' | 'å' | 'ā' | 'ą' => "a",
+        'ç' | 'ć' | 'č' => "c",
+        'ď' | 'đ' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ę' | 'ě' => "e",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' => "i",
+        'ł' => "l",
+        'ñ' | 'ń' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ō' | 'ő' => "o",
+        'ř' => "r",
+        'ś' | 'š' | 'ş' => "s",
+        'ť' | 'ţ' => "t",
+        'ù' | 'ú' | 'û' | 'ū' | 'ů' | 'ű' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
+}
+
+/// File in the export directory that lists the exported files, so that only these are removed
+/// when their frames no longer exist.
+const MANIFEST: &str = ".faster-beamer-export";
+
+/// Links the exported `files` (name and cached PDF) into `dir` and removes the files exported
+/// earlier for frames that no longer exist. Files whose PDF is missing, e.g. because the frame
+/// failed to compile, keep their previous link.
+pub fn export_frames(dir: &Path, files: &[(String, PathBuf)]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let manifest = dir.join(MANIFEST);
+    let previous = fs::read_to_string(&manifest).unwrap_or_default();
+    for name in previous.lines() {
+        let stale = !files.iter().any(|(exported, _)| exported == name);
+        // only plain file names, the manifest could have been edited
+        if stale && !name.is_empty() && Path::new(name).file_name() == Some(name.as_ref()) {
+            debug!("Removing stale export {}", name);
+            match fs::remove_file(dir.join(name)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+    }
+    for (name, pdf) in files {
+        let path = dir.join(name);
+        if !pdf.is_file() || fs::read_link(&path).ok().as_ref() == Some(pdf) {
+            continue;
+        }
+        if fs::symlink_metadata(&path).is_ok() {
+            fs::remove_file(&path)?;
+        }
+        ::symlink::symlink_file(pdf, &path)?;
+    }
+    let names: Vec<&str> = files
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .filter(|name| fs::symlink_metadata(dir.join(name)).is_ok())
+        .collect();
+    fs::write(manifest, names.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beamer::FrameSource;
+
+    #[test]
+    fn test_export_name() {
+        let frame = |content: &str| Frame::new(content, 0..content.len(), FrameSource::Fallback);
+
+        assert_eq!(
+            export_name(&frame(r"\begin{frame}{\emph{Über} the Introduction!}\end{frame}"), 2, 20),
+            "003-ueber-the-introduction.pdf"
+        );
+        assert_eq!(
+            export_name(&frame(r"\begin{frame}{Résumé: Ελλάδα}\end{frame}"), 0, 1),
+            "001-resume-ελλάδα.pdf"
+        );
+        assert_eq!(
+            export_name(&frame(r"\begin{frame}[label=sec:results]\end{frame}"), 0, 1200),
+            "0001-sec-results.pdf"
+        );
+        assert_eq!(export_name(&frame(r"\begin{frame}\end{frame}"), 9, 10), "010.pdf");
+    }
+
+    #[test]
+    fn test_export_frames() {
+        let cache = tempfile::tempdir().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let pdf = |name: &str| {
+            let path = cache.path().join(name);
+            fs::write(&path, name).unwrap();
+            path
+        };
+        let (a, b) = (pdf("a.pdf"), pdf("b.pdf"));
+        fs::write(dir.path().join("2024-report.pdf"), "user data").unwrap();
+
+        export_frames(
+            dir.path(),
+            &[("001-a.pdf".to_string(), a.clone()), ("002-b.pdf".to_string(), b)],
+        )
+        .unwrap();
+        let link = fs::read_link(dir.path().join("002-b.pdf")).unwrap();
+        assert_eq!(link, cache.path().join("b.pdf"));
+
+        // the second frame failed to compile and the first one was deleted
+        export_frames(
+            dir.path(),
+            &[("001-b.pdf".to_string(), cache.path().join("missing.pdf"))],
+        )
+        .unwrap();
+        assert!(!dir.path().join("001-a.pdf").exists());
+        assert!(!dir.path().join("002-b.pdf").exists());
+        assert!(!dir.path().join("001-b.pdf").exists());
+        assert!(dir.path().join("2024-report.pdf").exists());
+
+        export_frames(dir.path(), &[("001-a.pdf".to_string(), a.clone())]).unwrap();
+        fs::remove_file(&a).unwrap();
+        export_frames(dir.path(), &[("001-a.pdf".to_string(), a)]).unwrap();
+        // the previous link is kept while the frame does not compile
+        assert!(fs::symlink_metadata(dir.path().join("001-a.pdf")).is_ok());
+    }
+}
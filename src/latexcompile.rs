@@ -49,15 +49,22 @@
 //! ```
 //!
 
+use std::env;
+use std::ffi::OsString;
 use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Child, Command, Output, Stdio};
 use std::str;
-use tempfile::tempdir;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::{tempdir, TempDir};
 
 pub struct LatexRunOptions {
     double_compilation: bool,
     capture_stdout: bool,
+    bibliography: Option<String>,
 }
 
 impl LatexRunOptions {
@@ -65,8 +72,22 @@ impl LatexRunOptions {
         Self {
             double_compilation: false,
             capture_stdout: true,
+            bibliography: None,
         }
     }
+
+    /// Run LaTeX a second time, e.g. to resolve references.
+    pub fn double_compilation(mut self) -> Self {
+        self.double_compilation = true;
+        self
+    }
+
+    /// Run `tool` (bibtex or biber) after the first LaTeX run and LaTeX twice afterwards.
+    pub fn bibliography(mut self, tool: &str) -> Self {
+        self.bibliography = Some(tool.to_string());
+        self.double_compilation = true;
+        self
+    }
 }
 
 /// Specify all error cases with the fail api.
@@ -78,6 +99,8 @@ pub enum LatexError {
     Input(#[cause] std::io::Error),
     #[fail(display = "{}", _0)]
     Io(#[cause] std::io::Error),
+    #[fail(display = "Timed out after {} seconds.", _0)]
+    Timeout(u64),
 }
 
 /// result type alias idiom
@@ -86,16 +109,168 @@ type Result<T> = std::result::Result<T, LatexError>;
 /// An alias for a command line
 type Cmd = (String, Vec<String>);
 
+lazy_static! {
+    /// Number of LaTeX jobs that may still be started (`None` means unlimited).
+    static ref JOB_SLOTS: (Mutex<Option<usize>>, Condvar) = (Mutex::new(None), Condvar::new());
+}
+
+/// Limit the number of LaTeX processes running at the same time.
+pub fn set_max_jobs(jobs: usize) {
+    *JOB_SLOTS.0.lock().unwrap() = Some(jobs.max(1));
+    JOB_SLOTS.1.notify_all();
+}
+
+/// A slot for a running LaTeX process. The slot is freed again on drop.
+struct JobSlot;
+
+impl JobSlot {
+    fn acquire() -> JobSlot {
+        let (slots, freed) = &*JOB_SLOTS;
+        let mut free = slots.lock().unwrap();
+        while *free == Some(0) {
+            free = freed.wait(free).unwrap();
+        }
+        if let Some(free) = free.as_mut() {
+            *free -= 1;
+        }
+        JobSlot
+    }
+}
+
+impl Drop for JobSlot {
+    fn drop(&mut self) {
+        let (slots, freed) = &*JOB_SLOTS;
+        if let Some(free) = slots.lock().unwrap().as_mut() {
+            *free += 1;
+        }
+        freed.notify_one();
+    }
+}
+
+/// Restrict the address space of the process spawned by `cmd` to `bytes`.
+#[cfg(target_os = "linux")]
+fn limit_memory(cmd: &mut Command, bytes: u64) {
+    use std::os::unix::process::CommandExt;
+
+    let limit = libc::rlimit {
+        rlim_cur: bytes as libc::rlim_t,
+        rlim_max: bytes as libc::rlim_t,
+    };
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setrlimit(libc::RLIMIT_AS, &limit) == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        });
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn limit_memory(_cmd: &mut Command, _bytes: u64) {
+    warn!("Memory limits for LaTeX jobs are only supported on Linux");
+}
+
+/// Run the process spawned by `cmd` with the lowest scheduling priority.
+#[cfg(target_os = "linux")]
+fn lower_priority(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    unsafe {
+        cmd.pre_exec(|| {
+            // nice returns the new priority, failures are not worth aborting the job
+            libc::nice(19);
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lower_priority(_cmd: &mut Command) {}
+
+/// Read a pipe of a child process to the end on a separate thread.
+fn read_to_end<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _result = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+/// Wait for `child` and kill it if it is still running after `timeout`.
+fn wait_with_timeout(mut child: Child, timeout: Duration) -> Result<Output> {
+    let stdout = read_to_end(child.stdout.take());
+    let stderr = read_to_end(child.stderr.take());
+    let start = Instant::now();
+
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(LatexError::Io)? {
+            break status;
+        }
+        if start.elapsed() > timeout {
+            let _result = child.kill();
+            let _result = child.wait();
+            return Err(LatexError::Timeout(timeout.as_secs()));
+        }
+        thread::sleep(Duration::from_millis(50));
+    };
+    Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Run the process spawned by `cmd` once a job slot is free and kill it after `timeout`. Its
+/// address space is restricted to `memory_limit` bytes.
+pub fn execute(
+    mut cmd: Command,
+    timeout: Option<Duration>,
+    memory_limit: Option<u64>,
+    low_priority: bool,
+) -> Result<Output> {
+    let _slot = JobSlot::acquire();
+    if let Some(bytes) = memory_limit {
+        limit_memory(&mut cmd, bytes);
+    }
+    if low_priority {
+        lower_priority(&mut cmd);
+    }
+    let child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(LatexError::Io)?;
+    match timeout {
+        Some(timeout) => wait_with_timeout(child, timeout),
+        None => child.wait_with_output().map_err(LatexError::Io),
+    }
+}
+
 /// The latex input provides the needed files
 /// as tuple vector with name, buffer as tuple.
-#[derive(Debug, PartialEq)]
+/// Lazy inputs are only linked into the job directory of a compile.
+#[derive(Clone, Debug, PartialEq)]
 pub struct LatexInput {
     input: Vec<(String, Vec<u8>)>,
+    links: Vec<PathBuf>,
 }
 
 impl LatexInput {
     pub fn new() -> LatexInput {
-        LatexInput { input: vec![] }
+        LatexInput {
+            input: vec![],
+            links: vec![],
+        }
+    }
+
+    /// Add a buffer as input file `name`.
+    pub fn add(&mut self, name: &str, content: Vec<u8>) {
+        self.input.push((name.to_string(), content));
     }
 
     /// Add a single file as input.
@@ -147,47 +322,23 @@ impl LatexInput {
         Ok(())
     }
 
-    pub fn add_file_lazy(&mut self, file: PathBuf, dest_path: &Path) -> Result<()> {
+    /// Record a file which will be linked into the job directory of each compile.
+    pub fn add_file_lazy(&mut self, file: PathBuf) -> Result<()> {
         if file.is_file() {
-            let dest_file = dest_path.join(format!(
-                "./{}",
-                &file
-                    .to_str()
-                    .unwrap() // append input to cachedir
-                    .replace(":", "_") // Escape forbidden characters like ..cache_dir/c:/
-            ));
-            if !&dest_file.exists() {
-                match &dest_file.parent() {
-                    Some(p) => fs::create_dir_all(p).map_err(LatexError::Io)?,
-                    None => (),
-                }
-                let _result = ::symlink::symlink_file(file, dest_file);
-            }
+            self.links.push(file);
         }
         Ok(())
     }
 
-    pub fn add_folder_lazy(&mut self, folder: PathBuf, dest_path: &Path) -> Result<()> {
+    /// Record a folder which will be linked into the job directory of each compile.
+    pub fn add_folder_lazy(&mut self, folder: PathBuf) -> Result<()> {
         if folder.is_dir() {
-            let dest_folder = dest_path.join(format!(
-                "./{}",
-                &folder
-                    .to_str()
-                    .unwrap() // append input to cachedir
-                    .replace(":", "_") // Escape forbidden characters like ..cache_dir/c:/
-            ));
-            if !&dest_folder.exists() {
-                match &dest_folder.parent() {
-                    Some(p) => fs::create_dir_all(p).map_err(LatexError::Io)?,
-                    None => (),
-                }
-                let _result = ::symlink::symlink_dir(folder, dest_folder);
-            }
+            self.links.push(folder);
         }
         Ok(())
     }
 
-    pub fn from_lazy(s: &str, dest_path: &Path) -> Result<LatexInput> {
+    pub fn from_lazy(s: &str) -> Result<LatexInput> {
         let mut input = LatexInput::new();
         let path = PathBuf::from(s);
         let paths = fs::read_dir(path).map_err(LatexError::Input)?;
@@ -195,13 +346,46 @@ impl LatexInput {
         for path in paths {
             let p = path.map_err(LatexError::Input)?.path();
             if p.is_file() {
-                input.add_file_lazy(p, dest_path)?;
+                input.add_file_lazy(p)?;
             } else if p.is_dir() {
-                input.add_folder_lazy(p, dest_path)?;
+                input.add_folder_lazy(p)?;
             }
         }
         Ok(input)
     }
+
+    /// Add or drop the lazy input `path` depending on whether it still exists.
+    pub fn update_lazy(&mut self, path: &Path) -> Result<()> {
+        self.links.retain(|link| link != path);
+        if path.is_file() {
+            self.add_file_lazy(path.to_path_buf())
+        } else {
+            self.add_folder_lazy(path.to_path_buf())
+        }
+    }
+
+    /// Write all buffers and create links for all lazy inputs in `dir`.
+    fn materialize(&self, dir: &Path) -> Result<()> {
+        for (name, content) in &self.input {
+            if let Some(file_name) = Path::new(name).file_name() {
+                fs::write(dir.join(file_name), content).map_err(LatexError::Io)?;
+            }
+        }
+        for link in &self.links {
+            if let Some(file_name) = link.file_name() {
+                let dest = dir.join(file_name);
+                if dest.exists() {
+                    continue;
+                }
+                if link.is_dir() {
+                    ::symlink::symlink_dir(link, dest).map_err(LatexError::Io)?;
+                } else {
+                    ::symlink::symlink_file(link, dest).map_err(LatexError::Io)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 /// Provide a simple From conversion for &str to latex input.
@@ -226,7 +410,9 @@ impl<'a> From<&'a str> for LatexInput {
 
 /// The wrapper struct around some latex compiler.
 /// It provides a clean temporary enviroment for the
-/// latex compilation.
+/// latex compilation. Every compiler owns a job directory
+/// which holds the links to its inputs and all auxiliary files.
+/// Only the PDF, the log and the synctex file are promoted to `output_dir`.
 /// ```
 /// use std::fs::write;
 /// use std::collections::HashMap;
@@ -240,18 +426,64 @@ impl<'a> From<&'a str> for LatexInput {
 ///
 /// ```
 pub struct LatexCompiler {
-    pub working_dir: PathBuf,
+    pub output_dir: PathBuf,
+    /// Directory the compiler is started in. If set, auxiliary files are redirected to the
+    /// job directory via `-output-directory`. Otherwise the compiler runs in the job directory.
+    pub working_dir: Option<PathBuf>,
+    /// Wall-clock time after which a LaTeX run is killed.
+    pub timeout: Option<Duration>,
+    /// Maximal address space of a LaTeX run in bytes.
+    pub memory_limit: Option<u64>,
+    /// Run LaTeX with the lowest scheduling priority, e.g. for background jobs.
+    pub low_priority: bool,
+    /// Also keep the `AUXILIARY_EXTENSIONS` files of a successful run.
+    pub keep_auxiliary: bool,
+    job_dir: TempDir,
+    search_paths: Vec<PathBuf>,
     cmd: Cmd,
 }
 
+/// Environment variables that tell TeX, BibTeX and biber where to look for inputs.
+const SEARCH_PATH_VARIABLES: [&str; 3] = ["TEXINPUTS", "BIBINPUTS", "BSTINPUTS"];
+
+/// Build the value of a kpathsea search path variable like `TEXINPUTS`.
+/// `paths` are searched first, then the current value of `variable`. The trailing
+/// separator makes kpathsea append the default search path.
+pub fn search_path_value(variable: &str, paths: &[PathBuf]) -> OsString {
+    let separator = if cfg!(windows) { ";" } else { ":" };
+    let mut value = OsString::from(".");
+    for path in paths {
+        value.push(separator);
+        value.push(path);
+    }
+    value.push(separator);
+    if let Some(previous) = env::var_os(variable) {
+        value.push(previous);
+    }
+    value
+}
+
+/// Files of a LaTeX run that are kept after the job directory is removed.
+const PROMOTED_EXTENSIONS: [&str; 3] = ["pdf", "log", "synctex.gz"];
+
+/// Auxiliary files of a LaTeX run that can be kept in addition to `PROMOTED_EXTENSIONS`.
+pub const AUXILIARY_EXTENSIONS: [&str; 4] = ["aux", "bbl", "nav", "toc"];
+
 impl LatexCompiler {
     /// Create a new latex compiler wrapper
     pub fn new() -> Result<LatexCompiler> {
-        let dir = tempdir().map_err(LatexError::Io)?;
+        let job_dir = tempdir().map_err(LatexError::Io)?;
         let cmd = ("pdflatex".into(), vec!["-interaction=nonstopmode".into()]);
 
         Ok(LatexCompiler {
-            working_dir: dir.path().to_path_buf(),
+            output_dir: job_dir.path().to_path_buf(),
+            working_dir: None,
+            timeout: None,
+            memory_limit: None,
+            low_priority: false,
+            keep_auxiliary: false,
+            job_dir,
+            search_paths: vec![],
             cmd,
         })
     }
@@ -262,40 +494,125 @@ impl LatexCompiler {
         self
     }
 
+    /// Add a directory to `TEXINPUTS`, `BIBINPUTS` and `BSTINPUTS` of the compiler.
+    pub fn add_search_path(mut self, path: &Path) -> Self {
+        self.search_paths.push(path.to_owned());
+        self
+    }
+
     /// build the command-line
     fn get_cmd(&self, main_file: &str) -> Command {
         let mut cmd = Command::new(&self.cmd.0);
-        cmd.args(&self.cmd.1)
-            .arg(main_file)
-            .current_dir(&self.working_dir);
+        cmd.args(&self.cmd.1);
+        match &self.working_dir {
+            Some(working_dir) => {
+                cmd.arg(format!("-output-directory={}", self.job_dir.path().display()))
+                    .current_dir(working_dir);
+            }
+            None => {
+                cmd.current_dir(self.job_dir.path());
+            }
+        }
+        self.set_search_paths(&mut cmd);
+        cmd.arg(main_file);
         cmd
     }
 
+    /// build the command-line of the bibliography `tool` for the job `stem`
+    fn get_bibliography_cmd(&self, tool: &str, stem: &Path) -> Command {
+        let mut cmd = Command::new(tool);
+        cmd.current_dir(self.job_dir.path()).arg(stem);
+        self.set_search_paths(&mut cmd);
+        cmd
+    }
+
+    fn set_search_paths(&self, cmd: &mut Command) {
+        let mut search_paths = vec![self.job_dir.path().to_owned()];
+        search_paths.extend(self.search_paths.iter().cloned());
+        for variable in SEARCH_PATH_VARIABLES.iter() {
+            cmd.env(variable, search_path_value(variable, &search_paths));
+        }
+    }
+
+    /// Run `cmd` once a job slot is free, enforcing the time and memory limits.
+    fn execute(&self, cmd: Command) -> Result<Output> {
+        execute(cmd, self.timeout, self.memory_limit, self.low_priority)
+    }
+
+    /// Copy the results of a run from the job directory to `output_dir`.
+    /// Files are copied to a temporary name first so that concurrent readers
+    /// never see a partially written PDF.
+    fn promote(&self, stem: &Path, success: bool) -> Result<()> {
+        if self.output_dir == self.job_dir.path() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.output_dir).map_err(LatexError::Io)?;
+        let auxiliary: &[&str] = if self.keep_auxiliary {
+            &AUXILIARY_EXTENSIONS
+        } else {
+            &[]
+        };
+        for extension in PROMOTED_EXTENSIONS.iter().chain(auxiliary) {
+            if !success && *extension != "log" {
+                continue;
+            }
+            let file_name = format!("{}.{}", stem.to_string_lossy(), extension);
+            let source = self.job_dir.path().join(&file_name);
+            if source.is_file() {
+                let partial = self.output_dir.join(format!("{}.part", &file_name));
+                fs::copy(&source, &partial).map_err(LatexError::Io)?;
+                fs::rename(&partial, self.output_dir.join(&file_name)).map_err(LatexError::Io)?;
+            }
+        }
+        Ok(())
+    }
+
     pub fn run(
         &self,
         main: &str,
-        _input: &LatexInput,
+        input: &LatexInput,
         options: LatexRunOptions,
     ) -> Result<PathBuf> {
         assert!(options.capture_stdout);
 
+        input.materialize(self.job_dir.path())?;
+        let pdf = PathBuf::from(main);
+        let stem = PathBuf::from(pdf.file_stem().unwrap().to_str().unwrap());
+
         // first and second run
-        let output = self.get_cmd(main).output().map_err(LatexError::Io)?;
+        let output = self.execute(self.get_cmd(main)).map_err(|err| {
+            if let LatexError::Timeout(_) = err {
+                let _result = self.promote(&stem, false);
+            }
+            err
+        })?;
         if !output.status.success() {
             let err_msg = str::from_utf8(&output.stderr).unwrap().to_string();
             let std_out = str::from_utf8(&output.stdout).unwrap().to_string();
 
             error!("{}", &err_msg);
             error!("{}", &std_out);
+            self.promote(&stem, false)?;
             return Err(LatexError::LatexError(err_msg));
         };
+        if let Some(tool) = &options.bibliography {
+            match self.execute(self.get_bibliography_cmd(tool, &stem)) {
+                Ok(output) if output.status.success() => {}
+                Ok(output) => warn!(
+                    "{} failed:\n{}",
+                    tool,
+                    String::from_utf8_lossy(&output.stdout)
+                ),
+                Err(err) => warn!("Failed to run {}: {}", tool, err),
+            }
+            let _err_code = self.execute(self.get_cmd(main))?;
+        }
         if options.double_compilation {
-            let _err_code = self.get_cmd(main).output().map_err(LatexError::Io)?;
+            let _err_code = self.execute(self.get_cmd(main))?;
         }
 
         // get the output file
-        let pdf = PathBuf::from(main); //self.get_result_path(PathBuf::from(main))?;
-        let stem = PathBuf::from(pdf.file_stem().unwrap().to_str().unwrap());
-        Ok(self.working_dir.join(stem.with_extension("pdf")))
+        self.promote(&stem, true)?;
+        Ok(self.output_dir.join(stem.with_extension("pdf")))
     }
 }
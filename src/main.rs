@@ -4,20 +4,63 @@ extern crate log;
 extern crate lazy_static;
 #[macro_use]
 extern crate failure_derive;
+#[macro_use]
+extern crate clap;
 
 mod beamer;
+mod export;
 mod latexcompile;
+mod overview;
 mod parsing;
 mod process_file;
+mod render;
+mod report;
 mod tree_traversal;
 
 use clap::{App, Arg};
 use std::env;
 use std::env::current_dir;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 use std::{thread, time};
 use process_file::FasterBeamerError;
 
+fn is_number(value: String) -> Result<(), String> {
+    value
+        .parse::<u64>()
+        .map(|_| ())
+        .map_err(|_| format!("\"{}\" is not a number", value))
+}
+
+fn is_positive_number(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("\"{}\" is not a number", value)),
+    }
+}
+
+fn is_megabytes(value: String) -> Result<(), String> {
+    match value.parse::<u64>().map(|mb| mb.checked_mul(1024 * 1024)) {
+        Ok(Some(_)) => Ok(()),
+        Ok(None) => Err(format!("{} MB is too large", value)),
+        Err(_) => Err(format!("\"{}\" is not a number", value)),
+    }
+}
+
+fn is_overlay_step(value: String) -> Result<(), String> {
+    match value.as_str() {
+        "last" | "edit" => Ok(()),
+        _ => is_number(value),
+    }
+}
+
+fn is_grid(value: String) -> Result<(), String> {
+    overview::parse_grid(&value)
+        .map(|_| ())
+        .ok_or_else(|| format!("\"{}\" is not a grid like 4x3", value))
+}
+
 fn main() {
     if env::var("RUST_LOG").is_err() {
         let mut builder = pretty_env_logger::formatted_builder();
@@ -59,13 +102,167 @@ fn main() {
             Arg::with_name("frame-numbers")
                 .short("f")
                 .long("frame-numbers")
-                .help("Try to print correct frames numbers. This can harm cache performance when swapping frames."),
+                .help("Print correct frame and page numbers. Frames are recompiled when their numbers change."),
         )
         .arg(
             Arg::with_name("tree-sitter")
                 .short("t")
                 .long("tree-sitter")
-                .help("Use tree-sitter to parse LaTeX (instead of regexes)"),
+                .hidden(true)
+                .help("Use tree-sitter to parse LaTeX (this is the default now)"),
+        )
+        .arg(
+            Arg::with_name("regex")
+                .short("r")
+                .long("regex")
+                .help("Only use regexes to split frames (instead of tree-sitter)"),
+        )
+        .arg(
+            Arg::with_name("texinputs")
+                .long("texinputs")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("DIR")
+                .help("Additional directory to search for TeX, BibTeX and style files"),
+        )
+        .arg(
+            Arg::with_name("mirror-input")
+                .long("mirror-input")
+                .help("Link the files of the input directory into each compile job instead of setting TEXINPUTS"),
+        )
+        .arg(
+            Arg::with_name("frame-env")
+                .long("frame-env")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("ENV")
+                .help("Additional environment that produces a frame (like a custom frame environment)"),
+        )
+        .arg(
+            Arg::with_name("shell-escape")
+                .long("shell-escape")
+                .help("Allow LaTeX to run arbitrary shell commands (\\write18). Only use with trusted documents!"),
+        )
+        .arg(
+            Arg::with_name("shell-restricted")
+                .long("shell-restricted")
+                .conflicts_with("shell-escape")
+                .help("Allow LaTeX to run only the shell commands whitelisted by your TeX distribution"),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .takes_value(true)
+                .value_name("N")
+                .validator(is_positive_number)
+                .help("Maximum number of LaTeX jobs running in parallel (default: number of CPUs)"),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .validator(is_positive_number)
+                .help("Kill the LaTeX job of a frame after this wall-clock time"),
+        )
+        .arg(
+            Arg::with_name("memory-limit")
+                .long("memory-limit")
+                .takes_value(true)
+                .value_name("MB")
+                .validator(is_megabytes)
+                .help("Limit the memory of each LaTeX job (Linux only)"),
+        )
+        .arg(
+            Arg::with_name("full-compile")
+                .long("full-compile")
+                .help("Compile the whole document in the background to resolve references and citations"),
+        )
+        .arg(
+            Arg::with_name("only-frames")
+                .long("only-frames")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("LABELS")
+                .help("Only compile the frames with the given labels, like \\includeonlyframes"),
+        )
+        .arg(
+            Arg::with_name("overlay")
+                .long("overlay")
+                .takes_value(true)
+                .value_name("STEP")
+                .validator(is_overlay_step)
+                .help("Only show one overlay of the frame: a number, \"last\" or \"edit\" for the overlay at the last change"),
+        )
+        .arg(
+            Arg::with_name("mode")
+                .long("mode")
+                .takes_value(true)
+                .value_name("MODE")
+                .possible_values(&["presentation", "handout", "trans", "article"])
+                .help("Build the document in this mode instead of the one in the \\documentclass options"),
+        )
+        .arg(
+            Arg::with_name("notes")
+                .long("notes")
+                .takes_value(true)
+                .value_name("MODE")
+                .possible_values(&["hide", "show", "only", "second-screen"])
+                .help("Typeset the \\note pages of the frames: hide, show, only or second-screen"),
+        )
+        .arg(
+            Arg::with_name("report")
+                .long("report")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write a JSON report with the PDF, images, title and notes of each frame"),
+        )
+        .arg(
+            Arg::with_name("png")
+                .long("png")
+                .help("Render the pages of each frame to <hash>-<page>.png next to the cached PDFs"),
+        )
+        .arg(
+            Arg::with_name("dpi")
+                .long("dpi")
+                .takes_value(true)
+                .value_name("DPI")
+                .validator(is_number)
+                .requires("png")
+                .help("Resolution of the images rendered with --png (default: 96)"),
+        )
+        .arg(
+            Arg::with_name("overview")
+                .long("overview")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Write a PDF with all frames on a grid, with their numbers and titles"),
+        )
+        .arg(
+            Arg::with_name("overview-grid")
+                .long("overview-grid")
+                .takes_value(true)
+                .value_name("COLUMNSxROWS")
+                .validator(is_grid)
+                .requires("overview")
+                .help("Frames per page of the overview (default: 4x4)"),
+        )
+        .arg(
+            Arg::with_name("overview-last")
+                .long("overview-last")
+                .requires("overview")
+                .help("Show the last overlay of each frame on the overview instead of the first"),
+        )
+        .arg(
+            Arg::with_name("export-dir")
+                .long("export-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Link a PDF per frame into this directory, named like 003-introduction.pdf"),
         )
         .arg(
             Arg::with_name("OUTPUT")
@@ -79,6 +276,32 @@ fn main() {
         //)
         .get_matches();
 
+    if let Ok(jobs) = value_t!(matches, "jobs", usize) {
+        process_file::set_max_jobs(jobs);
+    }
+
+    // the united document would mix the note pages with slides of another size
+    if matches.is_present("unite")
+        && matches!(matches.value_of("notes"), Some("only") | Some("second-screen"))
+    {
+        error!("--unite only supports --notes hide or show");
+        std::process::exit(-1);
+    }
+
+    // articles are compiled as a whole and have no frames to unite, export or show
+    if matches.value_of("mode") == Some("article") {
+        let per_frame = ["unite", "pdfunite", "overlay", "report", "png", "overview", "export-dir"];
+        if let Some(arg) = per_frame.iter().find(|arg| matches.is_present(arg)) {
+            error!("--mode article does not support --{}", arg);
+            std::process::exit(-1);
+        }
+    }
+
+    if matches.is_present("png") && render::Renderer::find().is_none() {
+        error!("--png needs pdftoppm (poppler) or mutool (MuPDF) in the PATH");
+        std::process::exit(-1);
+    }
+
     let is_watch_mode = matches.is_present("watch");
     let input_file = matches.value_of("INPUT").unwrap();
 
@@ -97,7 +320,9 @@ fn main() {
 
     if is_watch_mode {
         use hotwatch::{Event, Hotwatch};
-        let matches = matches.clone();
+        let processing = Arc::new(Mutex::new(()));
+        let watch_processing = processing.clone();
+        let watch_matches = matches.clone();
 
         let mut hotwatch = Hotwatch::new().expect("Hotwatch failed to initialize.");
         hotwatch
@@ -105,16 +330,24 @@ fn main() {
                 Event::Write(file) | Event::NoticeRemove(file) => {
                     trace!("{:?} has changed.", file);
                     thread::sleep(time::Duration::from_millis(50));
-                    let input_file = matches.value_of("INPUT").unwrap();
+                    let input_file = watch_matches.value_of("INPUT").unwrap();
                     match (Path::new(&input_file).canonicalize(), file.canonicalize()) {
                         (Ok(file), Ok(changed_file)) if file == changed_file => {
                             let path_str = file.to_str().unwrap();
+                            let _processing = watch_processing.lock().unwrap();
                             info!("Processing {:?}.", &path_str);
-                            let _result = process_file::process_file(path_str, &matches);
+                            let _result = process_file::process_file(path_str, &watch_matches);
                         }
                         _ => {}
                     }
                 }
+                Event::Create(file) | Event::Remove(file) => {
+                    process_file::update_input_tree(&file);
+                }
+                Event::Rename(from, to) => {
+                    process_file::update_input_tree(&from);
+                    process_file::update_input_tree(&to);
+                }
                 _ => {
                     trace!("{:?}", event);
                 }
@@ -125,6 +358,11 @@ fn main() {
 
         loop {
             thread::sleep(time::Duration::from_millis(100));
+            if process_file::full_compile_finished() {
+                let _processing = processing.lock().unwrap();
+                info!("Processing {:?} with updated references.", input_file);
+                let _result = process_file::process_file(input_file, &matches);
+            }
         }
     }
 }
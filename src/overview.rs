@@ -0,0 +1,152 @@
+//
+// overview.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use regex::Regex;
+
+lazy_static! {
+    static ref CONTROL_WORD_REGEX: Regex = Regex::new(r"\\[a-zA-Z]+\*?\s*").unwrap();
+}
+
+/// A frame as it is shown on the overview.
+pub struct OverviewEntry<'a> {
+    pub title: Option<&'a str>,
+    /// File name of the compiled PDF of the frame in the job directory of the overview, or
+    /// `None` if it failed to compile
+    pub pdf: Option<String>,
+    /// Page of the PDF that is shown
+    pub page: usize,
+}
+
+/// Parses a grid size like `4x3` into columns and rows.
+pub fn parse_grid(value: &str) -> Option<(usize, usize)> {
+    let (columns, rows) = value.split_once('x')?;
+    match (columns.trim().parse(), rows.trim().parse()) {
+        (Ok(columns), Ok(rows)) if columns > 0 && rows > 0 => Some((columns, rows)),
+        _ => None,
+    }
+}
+
+/// Returns a LaTeX document that lays out the `entries` on a grid of `columns` × `rows` per
+/// page, each with its frame number and title.
+pub fn overview_document(entries: &[OverviewEntry], columns: usize, rows: usize) -> String {
+    let width = 0.96 / columns as f64;
+    let height = 0.8 / rows as f64;
+    let mut tex = String::from(concat!(
+        "\\documentclass{article}\n",
+        "\\usepackage[a4paper,landscape,margin=1cm]{geometry}\n",
+        "\\usepackage{graphicx}\n",
+        "\\pagestyle{empty}\n",
+        "\\setlength{\\parindent}{0pt}\n",
+        "\\begin{document}\n"
+    ));
+    for (page, chunk) in entries.chunks(columns * rows).enumerate() {
+        if page > 0 {
+            tex += "\\newpage\n";
+        }
+        for (row, row_entries) in chunk.chunks(columns).enumerate() {
+            if row > 0 {
+                tex += "\\par\\vfill\n";
+            }
+            for column in 0..columns {
+                let frame_idx = page * columns * rows + row * columns + column;
+                tex += &format!("\\begin{{minipage}}[t]{{{:.3}\\linewidth}}\\centering\n", width);
+                if let Some(entry) = row_entries.get(column) {
+                    tex += &match entry.pdf.as_deref().filter(|pdf| is_plain_file_name(pdf)) {
+                        Some(pdf) => format!(
+                            concat!(
+                                "\\fbox{{\\includegraphics[page={},width=\\linewidth,",
+                                "height={:.3}\\textheight,keepaspectratio]{{{}}}}}\\\\\n"
+                            ),
+                            entry.page, height, pdf
+                        ),
+                        None => "\\fbox{failed to compile}\\\\\n".to_string(),
+                    };
+                    tex += &format!(
+                        "\\scriptsize {}: {}\n",
+                        frame_idx + 1,
+                        plain_text(entry.title.unwrap_or(""))
+                    );
+                }
+                tex += "\\end{minipage}\\hfill\n";
+            }
+        }
+    }
+    tex + "\\end{document}\n"
+}
+
+/// Returns whether `name` can be passed to `\includegraphics` as it is.
+fn is_plain_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// Returns the text of the LaTeX code `text` without commands and braces, with the special
+/// characters of LaTeX escaped again.
+fn plain_text(text: &str) -> String {
+    let text = CONTROL_WORD_REGEX.replace_all(text, "");
+    let mut plain = String::new();
+    let mut chars = text.chars();
+    while let Some(mut c) = chars.next() {
+        // control symbols like `\%` stand for the character itself, `\\` breaks the line
+        if c == '\\' {
+            c = match chars.next() {
+                Some('\\') | None => ' ',
+                Some(symbol) => symbol,
+            };
+        }
+        match c {
+            '{' | '}' => {}
+            '#' | '$' | '%' | '&' | '_' => {
+                plain.push('\\');
+                plain.push(c);
+            }
+            '^' => plain += "\\textasciicircum{}",
+            '~' => plain += "\\textasciitilde{}",
+            _ => plain.push(c),
+        }
+    }
+    plain.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_grid() {
+        assert_eq!(parse_grid("4x3"), Some((4, 3)));
+        assert_eq!(parse_grid("0x3"), None);
+        assert_eq!(parse_grid("4"), None);
+    }
+
+    #[test]
+    fn test_plain_file_name() {
+        assert!(is_plain_file_name("0123abcd_2.pdf"));
+        assert!(!is_plain_file_name("my deck#1%.pdf"));
+    }
+
+    #[test]
+    fn test_overview_document() {
+        let entries: Vec<_> = (0..5)
+            .map(|i| OverviewEntry {
+                title: Some(if i == 4 { r"\alert {50\%} of\\$x_1$" } else { "Title" }),
+                pdf: if i == 3 { None } else { Some(format!("{}.pdf", i)) },
+                page: 1,
+            })
+            .collect();
+        let tex = overview_document(&entries, 2, 2);
+
+        assert_eq!(tex.matches("\\newpage").count(), 1);
+        // the last row is filled up with empty cells to keep the grid
+        assert_eq!(tex.matches("\\begin{minipage}").count(), 6);
+        assert!(tex.contains("[page=1,width=\\linewidth,height=0.400\\textheight"));
+        assert!(tex.contains("keepaspectratio]{2.pdf}}\\\\\n\\scriptsize 3: Title\n"));
+        assert!(tex.contains("\\fbox{failed to compile}\\\\\n\\scriptsize 4: "));
+        assert!(tex.contains("\\scriptsize 5: 50\\% of \\$x\\_1\\$\n"));
+        assert!(tex.ends_with("\\end{document}\n"));
+    }
+}
@@ -4,24 +4,80 @@
 // Distributed under terms of the MIT license.
 //
 
-use crate::tree_traversal::get_nodes_of_type;
+use crate::tree_traversal::{get_captures, get_nodes_of_type};
+use std::collections::HashMap;
 use std::fs;
-use tree_sitter::{Language, Node, Parser};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use tree_sitter::{InputEdit, Language, Node, Parser, Point, Query};
 
 extern "C" {
     fn tree_sitter_latex() -> Language;
 }
 
+const BEAMER_QUERIES: &str = include_str!("beamer.scm");
+
+lazy_static! {
+    /// Queries of `beamer.scm` restricted to the patterns of a single capture, by capture name.
+    static ref CAPTURE_QUERIES: Mutex<HashMap<String, Option<Arc<Query>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Returns the query for the patterns of `beamer.scm` that capture `capture_name`, so that
+/// wildcard patterns for other captures are not run. The queries are built once per name.
+fn capture_query(capture_name: &str) -> Option<Arc<Query>> {
+    let mut queries = CAPTURE_QUERIES.lock().unwrap();
+    queries
+        .entry(capture_name.to_owned())
+        .or_insert_with(|| {
+            let mut query = match Query::new(unsafe { tree_sitter_latex() }, BEAMER_QUERIES) {
+                Ok(query) => query,
+                Err(err) => {
+                    error!("Failed to load beamer.scm: {:?}", err);
+                    return None;
+                }
+            };
+            let capture = format!("@{}", capture_name);
+            for i in 0..query.pattern_count() {
+                let start = query.start_byte_for_pattern(i);
+                let end = if i + 1 < query.pattern_count() {
+                    query.start_byte_for_pattern(i + 1)
+                } else {
+                    BEAMER_QUERIES.len()
+                };
+                let pattern = &BEAMER_QUERIES[start..end];
+                let captures_name = pattern.match_indices(&capture).any(|(pos, _)| {
+                    !pattern[pos + capture.len()..]
+                        .starts_with(|c: char| c.is_alphanumeric() || c == '.' || c == '_')
+                });
+                if !captures_name {
+                    query.disable_pattern(i);
+                }
+            }
+            Some(Arc::new(query))
+        })
+        .clone()
+}
+
+#[derive(Clone)]
 pub struct ParsedFile {
     pub filename: String,
     pub file_content: String,
     pub syntax_tree: tree_sitter::Tree,
+    /// Byte ranges that changed compared to the previous version of the file,
+    /// if it was parsed incrementally.
+    pub changed_ranges: Option<Vec<Range<usize>>>,
 }
 
 impl ParsedFile {
-    pub fn new(filename: String) -> ParsedFile {
+    /// Reads and parses `filename`. If the `previous` version of the file is given,
+    /// it is parsed incrementally.
+    pub fn new(filename: String, previous: Option<&ParsedFile>) -> ParsedFile {
         let file_content = fs::read_to_string(&filename).expect("Failed to read file");
-        ParsedFile::from_string(filename, file_content)
+        match previous {
+            Some(previous) => ParsedFile::from_previous(previous, file_content),
+            None => ParsedFile::from_string(filename, file_content),
+        }
     }
 
     pub fn from_string(filename: String, file_content: String) -> ParsedFile {
@@ -37,6 +93,42 @@ impl ParsedFile {
             filename,
             file_content,
             syntax_tree: tree,
+            changed_ranges: None,
+        }
+    }
+
+    /// Parses `file_content` incrementally, reusing the syntax tree of `previous`
+    /// which has to be an older version of the same file.
+    pub fn from_previous(previous: &ParsedFile, file_content: String) -> ParsedFile {
+        let mut parser = Parser::new();
+        let language = unsafe { tree_sitter_latex() };
+
+        parser.set_language(language).unwrap();
+
+        let edit = compute_edit(&previous.file_content, &file_content);
+        let mut old_tree = previous.syntax_tree.clone();
+        if let Some(edit) = &edit {
+            old_tree.edit(edit);
+        }
+
+        let tree = parser
+            .parse(&file_content, Some(&old_tree))
+            .expect("Failed to parse file");
+
+        // `changed_ranges` only reports changes of the syntactic structure,
+        // so the edited text itself is always considered as changed
+        let mut changed_ranges: Vec<_> = old_tree
+            .changed_ranges(&tree)
+            .map(|r| r.start_byte..r.end_byte)
+            .collect();
+        if let Some(edit) = edit {
+            changed_ranges.push(edit.start_byte..edit.new_end_byte);
+        }
+        ParsedFile {
+            filename: previous.filename.clone(),
+            file_content,
+            syntax_tree: tree,
+            changed_ranges: Some(changed_ranges),
         }
     }
 
@@ -45,16 +137,113 @@ impl ParsedFile {
         get_nodes_of_type(root_node, node_type, false)
     }
 
+    /// Returns all nodes captured as `capture_name` by the queries in `beamer.scm`.
+    pub fn get_captures(&self, capture_name: &str) -> Vec<Node<'_>> {
+        match capture_query(capture_name) {
+            Some(query) => get_captures(&query, self.syntax_tree.root_node(), &self.file_content)
+                .into_iter()
+                .filter(|(name, _)| *name == capture_name)
+                .map(|(_, node)| node)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
     pub fn get_node_string(&self, node: &Node) -> &str {
         &self.file_content[node.start_byte()..node.end_byte()]
     }
 }
 
+/// Returns the single edit that transforms `old` into `new` or `None` if they are equal.
+pub fn compute_edit(old: &str, new: &str) -> Option<InputEdit> {
+    let mut start = old
+        .bytes()
+        .zip(new.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    if start == old.len() && start == new.len() {
+        return None;
+    }
+    while !old.is_char_boundary(start) {
+        start -= 1;
+    }
+
+    let max_suffix = old.len().min(new.len()) - start;
+    let mut suffix = old
+        .bytes()
+        .rev()
+        .zip(new.bytes().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !old.is_char_boundary(old.len() - suffix) {
+        suffix -= 1;
+    }
+
+    let old_end = old.len() - suffix;
+    let new_end = new.len() - suffix;
+    Some(InputEdit {
+        start_byte: start,
+        old_end_byte: old_end,
+        new_end_byte: new_end,
+        start_position: position_of(old, start),
+        old_end_position: position_of(old, old_end),
+        new_end_position: position_of(new, new_end),
+    })
+}
+
+/// Returns row and byte column of the byte offset `byte` in `content`.
+fn position_of(content: &str, byte: usize) -> Point {
+    let before = &content[..byte];
+    let row = before.matches('\n').count();
+    let column = byte - before.rfind('\n').map_or(0, |newline| newline + 1);
+    Point::new(row, column)
+}
+
 #[cfg(test)]
 mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_beamer_query_matches_grammar() {
+        assert!(capture_query("include").is_some());
+    }
+
+    #[test]
+    fn test_compute_edit() {
+        let edit = compute_edit("a\nbcd\ne", "a\nbXXd\ne").unwrap();
+        assert_eq!(edit.start_byte, 3);
+        assert_eq!(edit.old_end_byte, 4);
+        assert_eq!(edit.new_end_byte, 5);
+        assert_eq!(edit.start_position, Point::new(1, 1));
+        assert_eq!(edit.old_end_position, Point::new(1, 2));
+        assert_eq!(edit.new_end_position, Point::new(1, 3));
+
+        // insertion of a repeated character
+        let edit = compute_edit("aaa", "aaaa").unwrap();
+        assert_eq!(
+            (edit.start_byte, edit.old_end_byte, edit.new_end_byte),
+            (3, 3, 4)
+        );
+
+        // edits must not split multi-byte characters
+        let edit = compute_edit("xäy", "xöy").unwrap();
+        assert_eq!(
+            (edit.start_byte, edit.old_end_byte, edit.new_end_byte),
+            (1, 3, 3)
+        );
+
+        assert!(compute_edit("same", "same").is_none());
+
+        // deletions still have an edit
+        let edit = compute_edit("abc", "ac").unwrap();
+        assert_eq!(
+            (edit.start_byte, edit.old_end_byte, edit.new_end_byte),
+            (1, 2, 1)
+        );
+    }
+
     #[test]
     fn print_nodes_of_language() {
         let languages = vec![unsafe { tree_sitter_latex() }];
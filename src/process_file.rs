@@ -3,22 +3,37 @@
 // Copyright (C) 2019 seitz_local <seitz_local@lmeXX>
 // Distributed under terms of the GPLv3 license.
 //
-use crate::beamer::get_frames;
+use crate::beamer::{
+    assign_sections, assign_templates, attach_notes, bbl_entries, bibliography_commands,
+    bibliography_files, bibliography_tool, dead_regions, document_begin, extract_frames,
+    find_frames, find_references, frame_environments, frame_numbers, get_included_files,
+    implicit_frames, include_only_frames, included_frames, navigation, replace_frames,
+    replace_included_frames, resolve_againframes, set_document_mode, set_notes_option,
+    table_of_contents, uses_navigation, Frame, FrameSource, References,
+};
+use crate::overview::{overview_document, parse_grid, OverviewEntry};
 use crate::parsing;
+use crate::render::Renderer;
+use crate::report::{build_report, FrameReport};
 
-use log::Level::Trace;
-
-use crate::latexcompile::{LatexCompiler, LatexInput, LatexRunOptions};
+use crate::export::{export_frames, export_name};
+use crate::latexcompile::{
+    self, execute, search_path_value, LatexCompiler, LatexInput, LatexRunOptions,
+    AUXILIARY_EXTENSIONS,
+};
 use clap::ArgMatches;
 use indicatif::ProgressBar;
 use rayon::prelude::*;
 use regex::Regex;
 use std::env::current_dir;
 use std::fs::write;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 
 #[derive(PartialEq)]
@@ -31,17 +46,550 @@ pub enum FasterBeamerError {
 
 pub type Result<T> = ::std::result::Result<T, FasterBeamerError>;
 
-lazy_static! {
-    static ref FRAME_REGEX: Regex =
-        Regex::new(r"(?ms)^[\s\t]*?\\begin\{frame\}.*?^[\s\t]*?\\end\{frame\}").unwrap();
-}
 lazy_static! {
     static ref DOCUMENT_REGEX: Regex =
         Regex::new(r"(?ms)^[\s\t]*?\\begin\{document\}.*^[\s\t]*?\\end\{document\}").unwrap();
 }
 
 lazy_static! {
-    static ref PREVIOUS_FRAMES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    // the `\\write18 enabled.` banner at the start of every log is not an error
+    static ref SHELL_ESCAPE_REGEX: Regex = Regex::new(concat!(
+        r"(?i)invoke \S+ with the -+shell-escape|runsystem\(.*\)\.\.\.disabled",
+        r"|I can't write on file[^\n]*(?:\n[^\n]*){0,3}\\write18"
+    ))
+    .unwrap();
+}
+
+lazy_static! {
+    static ref PAGE_COUNT_REGEX: Regex =
+        Regex::new(r"Output written on[^(]*\((\d+)\s+pages?").unwrap();
+}
+
+lazy_static! {
+    static ref FULL_COMPILE: Mutex<FullCompileState> = Mutex::new(FullCompileState::default());
+}
+
+lazy_static! {
+    /// Pool of `--jobs` threads that compile the frames. Without a limit, rayon's global pool
+    /// with a thread per CPU is used.
+    static ref JOB_POOL: Mutex<Option<Arc<rayon::ThreadPool>>> = Mutex::new(None);
+}
+
+lazy_static! {
+    static ref PREVIOUS_FRAMES: Mutex<Vec<Frame>> = Mutex::new(Vec::new());
+}
+
+lazy_static! {
+    static ref PREVIOUS_PARSED_FILE: Mutex<Option<parsing::ParsedFile>> = Mutex::new(None);
+}
+
+lazy_static! {
+    static ref INCLUDED_FILES: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+}
+
+lazy_static! {
+    static ref INPUT_TREE: Mutex<Option<(PathBuf, LatexInput)>> = Mutex::new(None);
+}
+
+/// Limits the number of frames that are compiled at the same time and the number of LaTeX
+/// processes that run at the same time to `jobs`.
+pub fn set_max_jobs(jobs: usize) {
+    latexcompile::set_max_jobs(jobs);
+    match rayon::ThreadPoolBuilder::new().num_threads(jobs).build() {
+        Ok(pool) => *JOB_POOL.lock().unwrap() = Some(Arc::new(pool)),
+        Err(err) => error!("Failed to create a pool of {} jobs: {}", jobs, err),
+    }
+}
+
+/// Runs `op` in the pool of the frame compiles, so that its parallel iterators run at most
+/// `--jobs` LaTeX processes at a time.
+fn in_job_pool<R: Send>(op: impl FnOnce() -> R + Send) -> R {
+    let pool = JOB_POOL.lock().unwrap().clone();
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+/// Returns the inputs of all compile jobs for documents in `input_dir`.
+/// The directory is only scanned once per session, afterwards the tree is kept up to date
+/// by `update_input_tree`.
+fn input_tree(input_dir: &Path) -> LatexInput {
+    let mut tree = INPUT_TREE.lock().unwrap();
+    match &*tree {
+        Some((dir, input)) if dir == input_dir => input.clone(),
+        _ => {
+            trace!("Scanning input directory {:?}", input_dir);
+            let input = LatexInput::from_lazy(input_dir.to_str().unwrap())
+                .expect("Failed to create LatexInput");
+            *tree = Some((input_dir.to_owned(), input.clone()));
+            input
+        }
+    }
+}
+
+/// Updates the shared input tree after `path` was created, removed or renamed.
+pub fn update_input_tree(path: &Path) {
+    let mut tree = INPUT_TREE.lock().unwrap();
+    if let Some((dir, input)) = tree.as_mut() {
+        if path.parent() == Some(dir) {
+            trace!("Updating input tree for {:?}", path);
+            let _result = input.update_lazy(path);
+        }
+    }
+}
+
+/// Shell escape is disabled unless the user explicitly asks for it.
+fn shell_escape_arg(args: &ArgMatches) -> &'static str {
+    if args.is_present("shell-escape") {
+        "-shell-escape"
+    } else if args.is_present("shell-restricted") {
+        "-shell-restricted"
+    } else {
+        "-no-shell-escape"
+    }
+}
+
+/// Returns whether the LaTeX `log` shows an error because shell escape is disabled.
+fn needs_shell_escape(log: &str) -> bool {
+    SHELL_ESCAPE_REGEX.is_match(log)
+}
+
+/// Tells the user when `what` failed because a package like minted needs `\write18`.
+fn diagnose_shell_escape(log: &str, what: &str, args: &ArgMatches) {
+    if !args.is_present("shell-escape") && needs_shell_escape(log) {
+        error!(
+            "{} seems to require shell escape (e.g. for minted). \
+             Use --shell-escape if you trust the document.",
+            what
+        );
+    }
+}
+
+/// Replaces `output_file` by a link to `pdf`. Returns false if `pdf` does not exist.
+fn link_output(pdf: &Path, output_file: &str) -> bool {
+    if Path::new(&output_file).is_file() {
+        let _result =
+            ::std::fs::remove_file(&output_file).expect("Tried to delete previous output file");
+    }
+    if pdf.is_file() {
+        info!("Linking: {:?} -> {:?}", pdf, &output_file);
+        ::symlink::symlink_file(pdf, output_file).expect("Failed to create symlink to output file.");
+        true
+    } else {
+        false
+    }
+}
+
+/// Returns the number of pages written by the compile with the log file `log`.
+fn page_count(log: &Path) -> Option<usize> {
+    let log = std::fs::read(log).ok()?;
+    PAGE_COUNT_REGEX
+        .captures(&String::from_utf8_lossy(&log))
+        .and_then(|cap| cap[1].parse().ok())
+}
+
+/// Returns the path, without extension, where the compile of `stem` with the late inputs
+/// `stamp` is kept. It is reused when the inputs change back, e.g. when the frame numbers are
+/// restored after a frame was inserted and removed again.
+fn stamped_output(cache_subdir: &Path, stem: &str, stamp: &str) -> PathBuf {
+    cache_subdir
+        .join("stamped")
+        .join(format!("{}_{:x}", stem, md5::compute(stamp)))
+}
+
+/// Number of compiles with different late inputs that are kept for each frame.
+const STAMPED_COMPILES: usize = 4;
+
+/// Removes all but the `STAMPED_COMPILES` most recent stamped compiles of `stem`.
+fn prune_stamped_outputs(cache_subdir: &Path, stem: &str) {
+    let prefix = format!("{}_", stem);
+    let mut compiles: Vec<(std::time::SystemTime, PathBuf)> =
+        std::fs::read_dir(cache_subdir.join("stamped"))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension().is_some_and(|extension| extension == "pdf")
+                    && path.file_stem().and_then(|s| s.to_str()).is_some_and(|s| {
+                        s.len() == prefix.len() + 32 && s.starts_with(prefix.as_str())
+                    })
+            })
+            .filter_map(|path| Some((path.metadata().ok()?.modified().ok()?, path)))
+            .collect();
+    compiles.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+    for (_, path) in compiles.into_iter().skip(STAMPED_COMPILES) {
+        for extension in ["pdf", "log"] {
+            let _result = std::fs::remove_file(path.with_extension(extension));
+        }
+    }
+}
+
+/// Returns the source of the article built from the `body` of a document, starting at its
+/// `\begin{document}`, and the rewritten `preamble` precompiled as `format`.
+fn article_document(format: &str, preamble: &str, body: &str) -> String {
+    format!("%&{}\n{}\n{}", format, preamble, body)
+}
+
+/// Copies the PDF and the log of the compile at `from` to `to`, both without extension.
+fn copy_output(from: &Path, to: &Path) -> std::io::Result<()> {
+    for extension in ["pdf", "log"] {
+        std::fs::copy(from.with_extension(extension), to.with_extension(extension))?;
+    }
+    Ok(())
+}
+
+/// Inputs of a frame compile that are read when the frame is compiled instead of being part
+/// of the hashed frame document, so that they can change without invalidating the cache.
+struct LateInputs {
+    /// LaTeX code read right before the frame, e.g. `\setcounter` commands for the frame and
+    /// page numbers or `\bibcite` commands for the citations of the frame
+    commands: String,
+    /// Extensions of the files harvested from the full compile that the frame needs
+    harvested: Vec<&'static str>,
+    /// Whether the frame needs the `.bbl` file of the bibliography
+    bbl: bool,
+    /// `.toc` file synthesized from the sections of the document if the frame needs one that
+    /// was not harvested from the full compile
+    toc: Option<String>,
+    /// `.nav` file synthesized from the sections and frames of the document for the
+    /// navigation of the theme if it was not harvested from the full compile
+    nav: Option<String>,
+    /// Identifies the inputs that a cached frame was compiled with
+    stamp: String,
+    /// Whether `commands` set the frame and page numbers, so that compiles for other numbers
+    /// are worth keeping
+    counters: bool,
+}
+
+impl LateInputs {
+    fn new(
+        counters: String,
+        references: &References,
+        total_frames: bool,
+        harvested: &[(&'static str, String)],
+        bibliography: Option<&Bibliography>,
+        toc: Option<&str>,
+        nav: Option<&str>,
+    ) -> LateInputs {
+        // the navigation of the theme shows all sections and frames of the document
+        let navigation = nav.is_some();
+        let mut inputs = LateInputs {
+            counters: !counters.is_empty(),
+            commands: counters,
+            harvested: Vec::new(),
+            bbl: false,
+            toc: None,
+            nav: None,
+            stamp: String::new(),
+        };
+        let mut digests = String::new();
+        if let Some(bibliography) = bibliography {
+            for (key, bibcite) in &bibliography.bibcites {
+                if references.citations.contains(key) {
+                    inputs.commands += bibcite;
+                    inputs.commands += "\n";
+                }
+            }
+            if !references.citations.is_empty() || references.bibliography {
+                inputs.bbl = true;
+                digests += &format!(
+                    "% bbl {:x}\n",
+                    md5::compute(relevant_bbl(&bibliography.bbl, references))
+                );
+            }
+        }
+        for (extension, content) in harvested {
+            // only the parts of the files that the frame refers to decide about recompiles
+            let relevant = match *extension {
+                "aux" => content
+                    .lines()
+                    .filter(|line| {
+                        references
+                            .labels
+                            .iter()
+                            .chain(references.citations.iter())
+                            .any(|key| {
+                                line.contains(&format!("{{{}}}", key))
+                                    || line.contains(&format!("{{{}@", key))
+                            })
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                "bbl" if bibliography.is_none() => relevant_bbl(content, references),
+                "nav" if navigation => content.clone(),
+                "nav" if total_frames || references.total_frames => content
+                    .lines()
+                    .filter(|line| line.contains("inserttotalframenumber"))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                "toc" if references.table_of_contents || navigation => content.clone(),
+                _ => String::new(),
+            };
+            if !relevant.is_empty() {
+                inputs.harvested.push(extension);
+                digests += &format!("% {} {:x}\n", extension, md5::compute(&relevant));
+            }
+        }
+        if let (true, Some(toc)) = (references.table_of_contents || navigation, toc) {
+            if !inputs.harvested.contains(&"toc") {
+                digests += &format!("% toc {:x}\n", md5::compute(toc));
+                inputs.toc = Some(toc.to_string());
+            }
+        }
+        if let Some(nav) = nav {
+            if !inputs.harvested.contains(&"nav") {
+                digests += &format!("% nav {:x}\n", md5::compute(nav));
+                inputs.nav = Some(nav.to_string());
+            }
+        }
+        inputs.stamp = inputs.commands.clone() + &digests;
+        inputs
+    }
+}
+
+/// Returns the part of `bbl` that a frame with `references` depends on: all of it if the frame
+/// typesets the bibliography, otherwise the entries that it cites.
+fn relevant_bbl(bbl: &str, references: &References) -> String {
+    if references.bibliography {
+        bbl.to_string()
+    } else {
+        bbl_entries(bbl, &references.citations)
+    }
+}
+
+/// Returns the first of the `search_paths` that contains `file`.
+fn find_file(file: &str, search_paths: &[PathBuf]) -> Option<PathBuf> {
+    search_paths.iter().map(|p| p.join(file)).find(|p| p.is_file())
+}
+
+/// The bibliography of the document, built for the keys cited in its frames.
+struct Bibliography {
+    /// `.bbl` file with the cited entries
+    bbl: String,
+    /// `\bibcite` commands of BibTeX based bibliographies by citation key. They define the
+    /// citation labels in frames that do not typeset the bibliography themselves.
+    bibcites: Vec<(String, String)>,
+}
+
+impl Bibliography {
+    /// Processes the bibliography of the document for `citations` with `tool`. The result is
+    /// cached in the `output_dir` of `compiler` by the hash of the preamble, the bibliography
+    /// commands, the citations and the contents of the databases.
+    fn build(
+        tool: &str,
+        preamble: &str,
+        body: &str,
+        citations: &[String],
+        compiler: &LatexCompiler,
+        latex_input: &LatexInput,
+        search_paths: &[PathBuf],
+    ) -> Option<Bibliography> {
+        let commands: Vec<&str> = bibliography_commands(body)
+            .into_iter()
+            .filter(|(command, _, _)| *command != "addbibresource")
+            .map(|(_, text, _)| text)
+            .collect();
+        let document = format!(
+            "{}\n\\begin{{document}}\n\\nocite{{{}}}\n{}\n\\end{{document}}\n",
+            preamble,
+            citations.join(","),
+            commands.join("\n")
+        );
+
+        let mut key = md5::Context::new();
+        key.consume(tool);
+        key.consume(&document);
+        for file in bibliography_files(preamble)
+            .iter()
+            .chain(bibliography_files(body).iter())
+        {
+            match find_file(file, search_paths) {
+                Some(path) => key.consume(std::fs::read(path).unwrap_or_default()),
+                None => warn!("Could not find bibliography {}", file),
+            }
+        }
+        let stem = format!("{:x}", key.compute());
+        let bbl = compiler.output_dir.join(format!("{}.bbl", stem));
+
+        if !bbl.is_file() {
+            info!("Processing the bibliography with {}", tool);
+            std::fs::create_dir_all(&compiler.output_dir).ok()?;
+            let tex_file = compiler.output_dir.join(format!("{}.tex", stem));
+            write(&tex_file, &document).ok()?;
+            if let Err(err) = compiler.run(
+                &tex_file.to_string_lossy(),
+                latex_input,
+                LatexRunOptions::new().bibliography(tool),
+            ) {
+                error!("Failed to process the bibliography: {}", err);
+            }
+        }
+
+        let aux = std::fs::read_to_string(compiler.output_dir.join(format!("{}.aux", stem)))
+            .unwrap_or_default();
+        let bibcites = aux
+            .lines()
+            .filter(|line| line.starts_with("\\bibcite{"))
+            .filter_map(|line| {
+                let key = line["\\bibcite{".len()..].split('}').next()?;
+                Some((key.to_string(), line.to_string()))
+            })
+            .collect();
+        Some(Bibliography {
+            bbl: std::fs::read_to_string(bbl).ok()?,
+            bibcites,
+        })
+    }
+}
+
+/// A compile of the whole document whose `.aux`, `.bbl` and `.nav` files resolve references
+/// in the frames.
+struct FullCompile {
+    compiler: LatexCompiler,
+    input_file: PathBuf,
+    latex_input: LatexInput,
+    bibliography: Option<&'static str>,
+    /// Digest of the document and its included files
+    key: md5::Digest,
+}
+
+#[derive(Default)]
+struct FullCompileState {
+    running: bool,
+    /// Compile to start when the document has not changed for `FULL_COMPILE_DELAY`
+    pending: Option<FullCompile>,
+    /// When the pending compile was scheduled
+    scheduled: Option<Instant>,
+    /// Key of the last scheduled compile
+    last_key: Option<md5::Digest>,
+    /// Whether a compile changed its results since `full_compile_finished` was called
+    finished: bool,
+}
+
+impl FullCompile {
+    /// Compiles the document and returns whether the auxiliary files have changed.
+    fn run(&self) -> bool {
+        debug!("Compiling the whole document");
+        let stem = self.input_file.file_stem().unwrap().to_string_lossy();
+        let read_results = || -> Vec<Option<Vec<u8>>> {
+            AUXILIARY_EXTENSIONS
+                .iter()
+                .map(|extension| {
+                    std::fs::read(
+                        self.compiler
+                            .output_dir
+                            .join(format!("{}.{}", stem, extension)),
+                    )
+                    .ok()
+                })
+                .collect()
+        };
+        let previous = read_results();
+
+        let mut options = LatexRunOptions::new().double_compilation();
+        if let Some(tool) = self.bibliography {
+            options = options.bibliography(tool);
+        }
+        match self.compiler.run(
+            &self.input_file.to_string_lossy(),
+            &self.latex_input,
+            options,
+        ) {
+            Ok(_) => read_results() != previous,
+            Err(err) => {
+                warn!("Failed to compile the whole document: {}", err);
+                false
+            }
+        }
+    }
+}
+
+/// Time without changes of the document after which a full compile starts.
+const FULL_COMPILE_DELAY: Duration = Duration::from_secs(2);
+
+/// Runs `job` on a background thread once the running full compile has finished and the
+/// document has not changed for `FULL_COMPILE_DELAY`. Jobs for an unchanged document are
+/// dropped, a newer job replaces the pending one.
+fn schedule_full_compile(job: FullCompile) {
+    let mut state = FULL_COMPILE.lock().unwrap();
+    if state.last_key == Some(job.key) {
+        return;
+    }
+    state.last_key = Some(job.key);
+    state.pending = Some(job);
+    state.scheduled = Some(Instant::now());
+    if state.running {
+        return;
+    }
+    state.running = true;
+    thread::spawn(|| loop {
+        let mut state = FULL_COMPILE.lock().unwrap();
+        let idle = state.scheduled.map_or(FULL_COMPILE_DELAY, |scheduled| scheduled.elapsed());
+        if idle < FULL_COMPILE_DELAY {
+            drop(state);
+            thread::sleep(FULL_COMPILE_DELAY - idle);
+            continue;
+        }
+        let job = match state.pending.take() {
+            Some(job) => job,
+            None => {
+                state.running = false;
+                break;
+            }
+        };
+        drop(state);
+        let changed = job.run();
+        FULL_COMPILE.lock().unwrap().finished |= changed;
+    });
+}
+
+/// Returns whether a background compile of the whole document has produced new references
+/// since the last call. The frames should be processed again in that case.
+pub fn full_compile_finished() -> bool {
+    let mut state = FULL_COMPILE.lock().unwrap();
+    let finished = state.finished && !state.running;
+    if finished {
+        state.finished = false;
+    }
+    finished
+}
+
+/// Creates a compiler for the document, honoring the resource limits given in `args`.
+/// The compiler finds the files of the document via the `search_paths`, the first of which
+/// is the document directory. Without `mirror_input` the compiler runs in the document
+/// directory so that explicitly relative paths like `../shared/logo.pdf` resolve as usual.
+fn latex_compiler(
+    args: &ArgMatches,
+    search_paths: &[PathBuf],
+    mirror_input: bool,
+) -> LatexCompiler {
+    let compiler = LatexCompiler::new()
+        .unwrap()
+        .add_arg(shell_escape_arg(args))
+        .add_arg("-interaction=nonstopmode");
+    let mut compiler = search_paths
+        .iter()
+        .fold(compiler, |compiler, path| compiler.add_search_path(path));
+    if !mirror_input {
+        compiler.working_dir = search_paths.first().cloned();
+    }
+    compiler.timeout = timeout(args);
+    compiler.memory_limit = memory_limit(args);
+    compiler
+}
+
+/// Returns the time after which a LaTeX run is killed from `--timeout`.
+fn timeout(args: &ArgMatches) -> Option<Duration> {
+    value_t!(args, "timeout", u64).ok().map(Duration::from_secs)
+}
+
+/// Returns the maximal address space of a LaTeX run in bytes from `--memory-limit`.
+fn memory_limit(args: &ArgMatches) -> Option<u64> {
+    value_t!(args, "memory-limit", u64)
+        .ok()
+        .and_then(|mb| mb.checked_mul(1024 * 1024))
 }
 
 fn show_error_slide(cachedir: &Path, output_file: &str) {
@@ -56,9 +604,9 @@ fn show_error_slide(cachedir: &Path, output_file: &str) {
     if !error_pdf.exists() && write(&error_file, &error_frame[..]).is_ok() {
         let mut compiler = LatexCompiler::new()
             .unwrap()
-            .add_arg("-shell-escape")
+            .add_arg("-no-shell-escape")
             .add_arg("-interaction=nonstopmode");
-        compiler.working_dir = cachedir.to_owned();
+        compiler.output_dir = cachedir.to_owned();
 
         let _result = compiler.run(
             &error_file.canonicalize().unwrap().to_string_lossy(),
@@ -92,49 +640,123 @@ pub fn process_file(input_file: &str, args: &ArgMatches) -> Result<()> {
         return Err(FasterBeamerError::InputFileNotExistent);
     }
 
-    let parsed_file = parsing::ParsedFile::new(input_file.to_string());
+    let parsed_file = {
+        let mut previous = PREVIOUS_PARSED_FILE.lock().unwrap();
+        let previous_version = previous.as_ref().filter(|previous| {
+            Path::new(&previous.filename).canonicalize().ok() == input_path.canonicalize().ok()
+        });
+        let parsed_file = parsing::ParsedFile::new(input_file.to_string(), previous_version);
+        *previous = Some(parsed_file.clone());
+        parsed_file
+    };
     trace!("{}", parsed_file.syntax_tree.root_node().to_sexp());
 
-    let frame_nodes = if args.is_present("tree-sitter") {
-        get_frames(&parsed_file)
+    // range of the file name in the document, file name and path of the included files
+    let included_files: Vec<(Range<usize>, String, PathBuf)> = get_included_files(&parsed_file)
+        .into_iter()
+        .filter_map(|(range, file)| {
+            let path = input_dir.join(&file);
+            let path = vec![path.clone(), PathBuf::from(format!("{}.tex", path.display()))]
+                .into_iter()
+                .find(|p| p.is_file())
+                .and_then(|p| p.canonicalize().ok())?;
+            Some((range, file, path))
+        })
+        .collect();
+    *INCLUDED_FILES.lock().unwrap() =
+        included_files.iter().map(|(_, _, path)| path.clone()).collect();
+
+    let extra_frame_envs: Vec<String> = args
+        .values_of("frame-env")
+        .map(|envs| envs.map(String::from).collect())
+        .unwrap_or_default();
+    let frame_envs = frame_environments(&parsed_file.file_content, &extra_frame_envs);
+
+    let frame_ranges = if args.is_present("regex") {
+        find_frames(&parsed_file.file_content, &frame_envs)
+            .into_iter()
+            .map(|range| (range, FrameSource::Fallback))
+            .collect()
     } else {
-        Vec::new()
+        extract_frames(&parsed_file, &frame_envs)
     };
+    let mut frames: Vec<Frame> = frame_ranges
+        .into_iter()
+        .map(|(range, source)| Frame::new(&parsed_file.file_content, range, source))
+        .collect();
+    let implicit = implicit_frames(&parsed_file.file_content, &frames, &frame_envs);
+    // files included in a frame are compiled with the frame
+    let included: Vec<(Range<usize>, String, String)> = included_files
+        .iter()
+        .filter(|(range, _, _)| !frames.iter().any(|f| f.range.contains(&range.start)))
+        .filter_map(|(range, file, path)| {
+            Some((range.clone(), file.clone(), std::fs::read_to_string(path).ok()?))
+        })
+        .collect();
+    frames.extend(implicit);
+    frames.extend(included_frames(&included, &frame_envs));
+    // implicit and included frames come before the frames that start where they are added
+    frames.sort_by_key(|f| (f.range.start, f.range.end));
+    assign_sections(&parsed_file.file_content, &mut frames);
+    assign_templates(&parsed_file.file_content, &mut frames);
+    resolve_againframes(&mut frames);
+    attach_notes(&parsed_file.file_content, &mut frames);
 
-    let mut frames = Vec::with_capacity(frame_nodes.len());
-    if !frame_nodes.is_empty() {
-        for f in frame_nodes.iter() {
-            info!("Found {} frames with tree-sitter.", frame_nodes.len());
-            let node_string = parsed_file.get_node_string(&f);
-            frames.push(node_string.to_string());
-        }
-    } else {
-        for cap in FRAME_REGEX.captures_iter(&parsed_file.file_content) {
-            let frame_string = cap[0].to_string();
-            trace!("Frame {}:\n{}", frames.len() + 1, &frame_string);
-            frames.push(frame_string);
+    let only_frames: Option<Vec<String>> = match args.values_of("only-frames") {
+        Some(labels) => Some(
+            labels
+                .flat_map(|labels| labels.split(','))
+                .map(|label| label.trim().to_string())
+                .collect(),
+        ),
+        None => include_only_frames(&parsed_file.file_content),
+    };
+    if let Some(labels) = &only_frames {
+        frames.retain(|f| f.label().is_some_and(|label| labels.iter().any(|l| l == label)));
+        info!("Only including the frames {}", labels.join(", "));
+    }
+    for (frame_idx, frame) in frames.iter().enumerate() {
+        let line = parsed_file.file_content[..frame.range.start].matches('\n').count() + 1;
+        if frame.source == FrameSource::Fallback && !args.is_present("regex") {
+            debug!(
+                "{} (line {}) was found by the regex fallback.",
+                frame.describe(frame_idx),
+                line
+            );
         }
+        debug!(
+            "{:>4} {:<40} line {:<5} {} overlay(s){}{}",
+            frame_idx + 1,
+            frame.title.as_deref().unwrap_or("-"),
+            line,
+            frame.overlays,
+            if frame.options.fragile { ", fragile" } else { "" },
+            frame
+                .options
+                .label
+                .as_ref()
+                .map(|label| format!(", label={}", label))
+                .unwrap_or_default()
+        );
+        trace!("{}:\n{}", frame.describe(frame_idx), frame.text);
     }
-    info!("Found {} frames.", frames.len());
-
-    if log_enabled!(Trace) && args.is_present("tree-sitter") {
-        let root_node = parsed_file.syntax_tree.root_node();
-        let mut stack = vec![root_node];
-
-        while !stack.is_empty() {
-            let current_node = stack.pop().unwrap();
-            if current_node.kind() == "ERROR" {
-                error!(
-                    "\n{}:\n\t {}",
-                    current_node.kind(),
-                    parsed_file.get_node_string(&current_node),
-                );
-            }
+    info!(
+        "Found {} frames with {} overlays.",
+        frames.len(),
+        frames.iter().map(|f| f.overlays).sum::<usize>()
+    );
 
-            for i in (0..current_node.named_child_count()).rev() {
-                stack.push(current_node.named_child(i).unwrap());
-            }
+    let mut error_end = 0;
+    for error_node in parsed_file.get_nodes_of_type("ERROR".to_string()) {
+        if error_node.start_byte() < error_end {
+            continue;
         }
+        error_end = error_node.end_byte();
+        warn!(
+            "Could not parse line {} with tree-sitter. Falling back to regexes for frames there.",
+            error_node.start_position().row + 1
+        );
+        trace!("{}", parsed_file.get_node_string(&error_node));
     }
 
     //let document_env = tree_traversal::get_children(
@@ -150,12 +772,32 @@ pub fn process_file(input_file: &str, args: &ArgMatches) -> Result<()> {
     //"Could not find document environment with tree_sitter ({})",
     //input_file
     /*);*/
-    let find = parsed_file.file_content.find("\\begin{document}");
-    let preamble = match find {
+    let begin_document = document_begin(&parsed_file.file_content);
+    let preamble = match begin_document {
         Some(x) => Some(parsed_file.file_content[..x].to_owned()),
         None => None,
     }
     .unwrap_or_else(|| r"\documentclass[aspectratio=43,c,xcolor=dvipsnames]{beamer}".to_string());
+    // options given on the command line need a preamble and a format of their own
+    let custom_preamble = args.is_present("notes") || args.is_present("mode");
+    let preamble = match args.value_of("mode") {
+        Some(mode) => set_document_mode(&preamble, mode),
+        None => preamble,
+    };
+    // notes are typeset by the frame compiles and not by the united document
+    let united_preamble = preamble.clone();
+    let preamble = match args.value_of("notes") {
+        Some(mode) => set_notes_option(
+            &preamble,
+            match mode {
+                "show" => "show notes",
+                "only" => "show only notes",
+                "second-screen" => "show notes on second screen",
+                _ => "hide notes",
+            },
+        ),
+        None => preamble,
+    };
 
     let cachedir = dirs::cache_dir().expect("This OS is not supported").join("faster-beamer");
     std::fs::create_dir_all(&cachedir).map_err(|ref err| {
@@ -170,29 +812,58 @@ pub fn process_file(input_file: &str, args: &ArgMatches) -> Result<()> {
             .unwrap() // append input to cachedir
             .replace(":", "_") // Escape forbidden characters like ..cache_dir/c:/
     ));
+    // every mode gets a cache namespace of its own
+    let cache_subdir = match args.value_of("mode") {
+        Some(mode) => cache_subdir.join(mode),
+        None => cache_subdir,
+    };
+    std::fs::create_dir_all(&cache_subdir).map_err(|ref err| {
+        error!("Failed to create cache dir \"{}\": {}", cache_subdir.display(), err);
+        FasterBeamerError::IoError
+    })?;
 
     let preamble_hash = md5::compute(&preamble);
     let preamble_filename = format!("{:x}_{}", preamble_hash, args.is_present("draft"));
-    if input_path
-        .parent()
-        .unwrap()
-        .join(format!("{}.fmt", preamble_filename))
-        .is_file()
-    {
+    let mut search_paths = vec![input_dir.clone()];
+    if let Some(paths) = args.values_of("texinputs") {
+        search_paths.extend(
+            paths.map(|p| Path::new(p).canonicalize().unwrap_or_else(|_| PathBuf::from(p))),
+        );
+    }
+    let mirror_input = args.is_present("mirror-input");
+
+    if input_dir.join(format!("{}.fmt", preamble_filename)).is_file() {
         info!("Precompiled preamble already exists");
     } else {
         info!(
             "Precompiling preamble {:?}",
-            input_path.join(format!("{}.fmt", preamble_filename))
+            input_dir.join(format!("{}.fmt", preamble_filename))
         );
-        let output = Command::new("pdflatex")
-            .arg("-shell-escape")
+        let preamble_source = if custom_preamble {
+            let source = cache_subdir.join(format!("{}.tex", preamble_filename));
+            write(
+                &source,
+                format!("{}\n\\begin{{document}}\n\\end{{document}}\n", preamble),
+            )
+            .map_err(|ref err| {
+                error!("Failed to write preamble \"{}\": {}", source.display(), err);
+                FasterBeamerError::IoError
+            })?;
+            source.into_os_string()
+        } else {
+            input_path.file_name().unwrap().to_os_string()
+        };
+        let mut command = Command::new("pdflatex");
+        command
+            .arg(shell_escape_arg(args))
             .arg("-ini")
             .arg(format!("-jobname=\"{}\"", preamble_filename))
             .arg("\"&pdflatex\"")
             .arg("mylatexformat.ltx")
-            .arg(&input_file)
-            .output();
+            .arg(preamble_source)
+            .current_dir(&input_dir)
+            .env("TEXINPUTS", search_path_value("TEXINPUTS", &search_paths));
+        let output = execute(command, timeout(args), memory_limit(args), false);
         match output {
             Err(e) => {
                 error!("Failed to compile preamble!\n{}", e);
@@ -206,6 +877,11 @@ pub fn process_file(input_file: &str, args: &ArgMatches) -> Result<()> {
                     "Failed to compile preamble! {}",
                     str::from_utf8(&output.stderr).unwrap()
                 );
+                diagnose_shell_escape(
+                    &String::from_utf8_lossy(&output.stdout),
+                    "The preamble",
+                    args,
+                );
                 show_error_slide(&cachedir, output_file);
 
                 *PREVIOUS_FRAMES.lock().unwrap() = Vec::new();
@@ -213,40 +889,139 @@ pub fn process_file(input_file: &str, args: &ArgMatches) -> Result<()> {
             }
             _ => {}
         };
+        update_input_tree(&input_dir.join(format!("{}.fmt", preamble_filename)));
     }
+    let latex_input = if mirror_input {
+        input_tree(&input_dir)
+    } else {
+        LatexInput::new()
+    };
 
-    let mut generated_documents = Vec::new();
-    let mut command = &mut Command::new("pdfunite");
-    for (frame_idx, f) in frames.iter().enumerate() {
-        let frame_idx_str = if correct_frame_numbers {
-            format!("{}", frame_idx)
-        } else {
-            format!("{}", 0)
+    // an article is not a sequence of slides, so the whole document is compiled with the
+    // format of its preamble instead of the frames
+    if args.value_of("mode") == Some("article") {
+        let document = match begin_document {
+            Some(begin) => article_document(
+                &preamble_filename,
+                &preamble,
+                &parsed_file.file_content[begin..],
+            ),
+            None => {
+                error!("--mode article needs a document environment");
+                show_error_slide(&cachedir, output_file);
+                return Err(FasterBeamerError::CompileError);
+            }
         };
+        let mut key = md5::Context::new();
+        key.consume(&document);
+        for file in INCLUDED_FILES.lock().unwrap().iter() {
+            key.consume(std::fs::read(file).unwrap_or_default());
+        }
+        for file in bibliography_files(&parsed_file.file_content) {
+            if let Some(path) = find_file(&file, &search_paths) {
+                key.consume(std::fs::read(path).unwrap_or_default());
+            }
+        }
+        let article_dir = cache_subdir.join("document");
+        let article_stem = format!("{:x}", key.compute());
+        let article_tex = article_dir.join(format!("{}.tex", article_stem));
+        let article_pdf = article_dir.join(format!("{}.pdf", article_stem));
+        if article_pdf.is_file() {
+            info!("Article is already compiled");
+        } else {
+            // only the latest article is kept
+            for entry in std::fs::read_dir(&article_dir).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if path.file_stem().is_some_and(|stem| stem != article_stem.as_str()) {
+                    let _result = std::fs::remove_file(path);
+                }
+            }
+            std::fs::create_dir_all(&article_dir)
+                .and_then(|_| write(&article_tex, &document))
+                .map_err(|ref err| {
+                    error!("Failed to write \"{}\": {}", article_tex.display(), err);
+                    FasterBeamerError::IoError
+                })?;
+            let mut compiler = latex_compiler(args, &search_paths, mirror_input);
+            compiler.output_dir = article_dir.clone();
+            // the timeout is meant for single frames, not for the whole document
+            compiler.timeout = None;
+            let mut options = LatexRunOptions::new().double_compilation();
+            if let Some(tool) = bibliography_tool(&parsed_file.file_content) {
+                options = options.bibliography(tool);
+            }
+            if let Err(err) = compiler.run(
+                &article_tex.canonicalize().unwrap().to_string_lossy(),
+                &latex_input,
+                options,
+            ) {
+                error!("Failed to compile the article!\n{}", err);
+            }
+        }
+        if !link_output(&article_pdf, output_file) {
+            error!("Compilation failed!");
+            show_error_slide(&cachedir, output_file);
+            return Err(FasterBeamerError::CompileError);
+        }
+        return Ok(());
+    }
+
+    let mut generated_documents: Vec<(String, String)> = Vec::new();
+    let mut command = &mut Command::new("pdfunite");
+    for f in frames.iter() {
+        // Counters and citation labels are read from a separate input so that they are not
+        // part of the hash.
         let compile_string = format!("%&{}\n", preamble_filename)
             + &preamble
             + "\n\\begin{document}\n"
-            + "\\addtocounter{framenumber}{"
-            + &frame_idx_str
-            + "}\n"
-            + &f
+            + &f.section_replay()
+            + &f.templates
+            + "\\InputIfFileExists{\\jobname.late}{}{}\n"
+            + &f.compile_text()
             + "\n\\end{document}\n";
 
-        let hash = md5::compute(&compile_string);
-        let output = cache_subdir.join(format!("{:x}.pdf", hash));
-        generated_documents.push((hash, compile_string));
+        let hash = format!("{:x}", md5::compute(&compile_string));
+        // identical frames may still need different counters
+        let duplicates = generated_documents
+            .iter()
+            .filter(|(stem, _)| stem.starts_with(&hash))
+            .count();
+        let stem = if duplicates == 0 {
+            hash
+        } else {
+            format!("{}_{}", hash, duplicates + 1)
+        };
+        let output = cache_subdir.join(format!("{}.pdf", stem));
+        generated_documents.push((stem, compile_string));
 
         command = command.arg(output.to_str().unwrap());
     }
+    let restart_in_appendix = preamble.contains("appendixnumberbeamer");
 
-    trace!("Comparing frames");
     let mut first_changed_frame = 0;
-    for frame_pair in frames.iter().zip((*PREVIOUS_FRAMES.lock().unwrap()).iter()) {
-        match frame_pair {
-            (lhs, rhs) if lhs != rhs => {
-                break;
+    if let Some(changed_ranges) = &parsed_file.changed_ranges {
+        trace!("Looking for frames in changed ranges {:?}", changed_ranges);
+        first_changed_frame = frames
+            .iter()
+            .position(|f| {
+                changed_ranges
+                    .iter()
+                    .any(|c| f.is_changed_by(c))
+            })
+            .unwrap_or(frames.len());
+    } else {
+        trace!("Comparing frames");
+        for frame_pair in frames.iter().zip((*PREVIOUS_FRAMES.lock().unwrap()).iter()) {
+            match frame_pair {
+                (lhs, rhs)
+                    if lhs.text != rhs.text
+                        || lhs.sections != rhs.sections
+                        || lhs.templates != rhs.templates =>
+                {
+                    break;
+                }
+                _ => first_changed_frame += 1,
             }
-            _ => first_changed_frame += 1,
         }
     }
     debug!(
@@ -255,54 +1030,486 @@ pub fn process_file(input_file: &str, args: &ArgMatches) -> Result<()> {
         frames.len()
     );
 
-    let progress_bar = ProgressBar::new(generated_documents.len() as u64);
+    let full_compile = if args.is_present("full-compile") {
+        let mut compiler = latex_compiler(args, &search_paths, mirror_input);
+        compiler.output_dir = cache_subdir.join("full");
+        // the timeout is meant for single frames, not for the whole document
+        compiler.timeout = None;
+        compiler.low_priority = true;
+        compiler.keep_auxiliary = true;
+        let mut key = md5::Context::new();
+        key.consume(&preamble);
+        key.consume(&parsed_file.file_content);
+        for file in INCLUDED_FILES.lock().unwrap().iter() {
+            key.consume(std::fs::read(file).unwrap_or_default());
+        }
+        // edited databases need another run of BibTeX or biber
+        for file in bibliography_files(&parsed_file.file_content) {
+            if let Some(path) = find_file(&file, &search_paths) {
+                key.consume(std::fs::read(path).unwrap_or_default());
+            }
+        }
+        // with options from the command line, a copy of the document with their preamble is
+        // compiled
+        let input_file = match begin_document {
+            Some(begin) if custom_preamble => {
+                let document = cache_subdir.join(input_path.file_name().unwrap());
+                match write(&document, preamble.clone() + &parsed_file.file_content[begin..]) {
+                    Ok(()) => document,
+                    Err(err) => {
+                        warn!("Failed to write \"{}\": {}", document.display(), err);
+                        input_path.canonicalize().unwrap()
+                    }
+                }
+            }
+            _ => input_path.canonicalize().unwrap(),
+        };
+        Some(FullCompile {
+            compiler,
+            input_file,
+            latex_input: latex_input.clone(),
+            bibliography: bibliography_tool(&parsed_file.file_content),
+            key: key.compute(),
+        })
+    } else {
+        None
+    };
+    let full_compile = match full_compile {
+        // without watch mode there is no idle time, so references are resolved up front
+        Some(job) if !args.is_present("watch") => {
+            job.run();
+            None
+        }
+        job => job,
+    };
+    let harvested: Vec<(&str, String)> = if args.is_present("full-compile") {
+        let stem = input_path.file_stem().unwrap().to_string_lossy();
+        AUXILIARY_EXTENSIONS
+            .iter()
+            .filter_map(|extension| {
+                std::fs::read_to_string(
+                    cache_subdir
+                        .join("full")
+                        .join(format!("{}.{}", stem, extension)),
+                )
+                .ok()
+                .map(|content| (*extension, content))
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let preamble_total_frames =
+        find_references(&preamble, &dead_regions(&preamble)).total_frames;
+    let needs_nav = uses_navigation(&preamble);
+    // the navigation marks the current frame by its page number
+    let correct_frame_numbers = correct_frame_numbers || needs_nav;
 
-    generated_documents
-        .par_iter()
-        .enumerate()
-        .for_each(|(frame_idx, (hash, tex_content))| {
-            let pdf = cache_subdir.join(format!("{:x}.pdf", hash));
+    let bibliography = bibliography_tool(&parsed_file.file_content).and_then(|tool| {
+        let mut citations: Vec<String> = Vec::new();
+        for key in frames.iter().flat_map(|f| f.references.citations.iter()) {
+            if !citations.contains(key) {
+                citations.push(key.clone());
+            }
+        }
+        if citations.is_empty() {
+            return None;
+        }
+        let mut compiler = latex_compiler(args, &search_paths, mirror_input);
+        compiler.output_dir = cache_subdir.join("bibliography");
+        compiler.keep_auxiliary = true;
+        Bibliography::build(
+            tool,
+            &preamble,
+            &parsed_file.file_content[begin_document.unwrap_or(0)..],
+            &citations,
+            &compiler,
+            &latex_input,
+            &search_paths,
+        )
+    });
 
-            if pdf.is_file() {
-                trace!("{} is already compiled!", pdf.to_str().unwrap_or("???"));
-            } else {
-                let latex_input = LatexInput::from_lazy(
-                    input_dir.canonicalize().unwrap().to_str().unwrap(),
-                    &cachedir,
+    // Frame numbers and the pages in the table of contents depend on the page counts of the
+    // frames before. These are known from the logs of previous compiles or estimated from the
+    // overlays, so after compiling new frames the numbers may have to be corrected once.
+    let needs_toc = needs_nav || frames.iter().any(|f| f.references.table_of_contents);
+    let late_inputs = || -> Vec<LateInputs> {
+        let pages: Vec<usize> = if correct_frame_numbers || needs_toc {
+            generated_documents
+                .iter()
+                .zip(frames.iter())
+                .map(|((stem, _), f)| {
+                    page_count(&cache_subdir.join(format!("{}.log", stem))).unwrap_or(f.overlays)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let counters: Vec<String> = if correct_frame_numbers {
+            frame_numbers(&frames, &pages, restart_in_appendix)
+                .into_iter()
+                .map(|(framenumber, page)| {
+                    format!(
+                        "\\setcounter{{framenumber}}{{{}}}\\setcounter{{page}}{{{}}}\n",
+                        framenumber,
+                        page + 1
+                    )
+                })
+                .collect()
+        } else {
+            vec![String::new(); frames.len()]
+        };
+        let toc = if needs_toc {
+            Some(table_of_contents(&parsed_file.file_content, &frames, &pages))
+        } else {
+            None
+        };
+        let nav = if needs_nav {
+            Some(navigation(&parsed_file.file_content, &frames, &pages))
+        } else {
+            None
+        };
+        frames
+            .iter()
+            .zip(counters)
+            .map(|(f, counters)| {
+                LateInputs::new(
+                    counters,
+                    &f.references,
+                    preamble_total_frames,
+                    &harvested,
+                    bibliography.as_ref(),
+                    toc.as_deref(),
+                    nav.as_deref(),
                 )
-                .expect("Failed to create LatexInput");
+            })
+            .collect()
+    };
+    let is_compiled = |stem: &str, inputs: &LateInputs| {
+        cache_subdir.join(format!("{}.pdf", stem)).is_file()
+            && std::fs::read_to_string(cache_subdir.join(format!("{}.stamp", stem)))
+                .unwrap_or_default()
+                == inputs.stamp
+    };
+    let compile_frame = |frame_idx: usize, stem: &str, tex_content: &str, inputs: &LateInputs| {
+        let output = cache_subdir.join(stem);
+        let stamped = Some(stamped_output(&cache_subdir, stem, &inputs.stamp))
+            .filter(|_| inputs.counters);
+        if let Some(stamped) = &stamped {
+            if stamped.with_extension("pdf").is_file() && copy_output(stamped, &output).is_ok() {
+                trace!("Reusing the compile of {} with the same late inputs", stem);
+                let _result = write(cache_subdir.join(format!("{}.stamp", stem)), &inputs.stamp);
+                return;
+            }
+        }
+        let temp_file = cache_subdir.join(format!("{}.tex", stem));
+
+        if write(&temp_file, tex_content).is_ok() {
+            let mut compiler = latex_compiler(args, &search_paths, mirror_input);
+            compiler.output_dir = cache_subdir.clone();
 
-                let temp_file = cache_subdir.join(format!("{:x}.tex", hash));
+            let mut latex_input = latex_input.clone();
+            latex_input.add(
+                &format!("{}.late", stem),
+                inputs.commands.as_bytes().to_vec(),
+            );
+            if let (true, Some(bibliography)) = (inputs.bbl, &bibliography) {
+                latex_input.add(
+                    &format!("{}.bbl", stem),
+                    bibliography.bbl.as_bytes().to_vec(),
+                );
+            }
+            if let Some(toc) = &inputs.toc {
+                latex_input.add(&format!("{}.toc", stem), toc.as_bytes().to_vec());
+            }
+            if let Some(nav) = &inputs.nav {
+                latex_input.add(&format!("{}.nav", stem), nav.as_bytes().to_vec());
+            }
+            for (extension, content) in harvested.iter() {
+                if inputs.harvested.contains(extension) {
+                    latex_input.add(
+                        &format!("{}.{}", stem, extension),
+                        content.as_bytes().to_vec(),
+                    );
+                }
+            }
+            // labels that the bibliography defines are only known in a second run
+            let options = if inputs.bbl && frames[frame_idx].references.bibliography {
+                LatexRunOptions::new().double_compilation()
+            } else {
+                LatexRunOptions::new()
+            };
+            let result = compiler.run(
+                &temp_file.canonicalize().unwrap().to_string_lossy(),
+                &latex_input,
+                options,
+            );
+            if result.is_ok() {
+                trace!("Compiled file {}", &temp_file.to_str().unwrap());
+                let _result = write(cache_subdir.join(format!("{}.stamp", stem)), &inputs.stamp);
+                if let Some(stamped) = &stamped {
+                    match std::fs::create_dir_all(stamped.parent().unwrap())
+                        .and_then(|_| copy_output(&output, stamped))
+                    {
+                        Ok(()) => prune_stamped_outputs(&cache_subdir, stem),
+                        Err(err) => debug!("Failed to keep the compile of {}: {}", stem, err),
+                    }
+                }
+            } else {
+                error!(
+                    "Failed to compile {} ({})",
+                    frames[frame_idx].describe(frame_idx),
+                    &temp_file.to_str().unwrap()
+                );
+                error!("{}", frames[frame_idx].text);
+                error!("{}", result.err().unwrap());
+                if let Ok(log) = std::fs::read(cache_subdir.join(format!("{}.log", stem))) {
+                    diagnose_shell_escape(
+                        &String::from_utf8_lossy(&log),
+                        &frames[frame_idx].describe(frame_idx),
+                        args,
+                    );
+                }
+            };
+        }
+    };
 
-                if write(&temp_file, &tex_content).is_ok() {
-                    let mut compiler = LatexCompiler::new()
-                        .unwrap()
-                        .add_arg("-shell-escape")
-                        .add_arg("-interaction=nonstopmode");
-                    compiler.working_dir = temp_file.parent().unwrap().canonicalize().unwrap();
+    let frame_inputs = late_inputs();
 
-                    let result = compiler.run(
-                        &temp_file.canonicalize().unwrap().to_string_lossy(),
-                        &latex_input,
-                        LatexRunOptions::new(),
+    let shown_frame = if first_changed_frame < frames.len() {
+        first_changed_frame
+    } else {
+        0
+    };
+    // A single overlay of the shown frame is compiled and shown before all other frames.
+    let preview = match args.value_of("overlay") {
+        Some(step)
+            if !args.is_present("pdfunite")
+                && !args.is_present("unite")
+                && shown_frame < frames.len() =>
+        {
+            let frame = &frames[shown_frame];
+            let slide = match step {
+                "last" => frame.overlays,
+                "edit" => parsed_file
+                    .changed_ranges
+                    .iter()
+                    .flatten()
+                    .find(|c| frame.is_changed_by(c))
+                    .map_or(frame.overlays, |c| {
+                        frame.overlay_at(c.start.saturating_sub(frame.range.start))
+                    }),
+                n => n.parse().unwrap_or(1).max(1),
+            };
+            match frame.restrict_to_overlay(slide) {
+                Some(restricted) => {
+                    let (stem, tex_content) = &generated_documents[shown_frame];
+                    let text = frame.compile_text();
+                    let at = tex_content.rfind(&text).unwrap();
+                    let preview_tex = format!(
+                        "{}{}{}",
+                        &tex_content[..at],
+                        restricted,
+                        &tex_content[at + text.len()..]
                     );
-                    if result.is_ok() {
-                        trace!("Compiled file {}", &temp_file.to_str().unwrap());
-                    } else {
-                        error!(
-                            "Failed to compile frame {} ({})",
-                            frame_idx,
-                            &temp_file.to_str().unwrap()
+                    let preview_stem = format!("{}_overlay{}", stem, slide);
+                    if !is_compiled(&preview_stem, &frame_inputs[shown_frame]) {
+                        info!(
+                            "Compiling overlay {} of {}",
+                            slide,
+                            frame.describe(shown_frame)
+                        );
+                        compile_frame(
+                            shown_frame,
+                            &preview_stem,
+                            &preview_tex,
+                            &frame_inputs[shown_frame],
                         );
-                        error!("{}", frames[frame_idx]);
-                        error!("{}", result.err().unwrap());
-                    };
+                    }
+                    let preview_pdf = cache_subdir.join(format!("{}.pdf", preview_stem));
+                    if link_output(&preview_pdf, output_file) {
+                        Some(preview_pdf)
+                    } else {
+                        None
+                    }
                 }
-            };
-            progress_bar.inc(1);
-        });
+                None => {
+                    debug!(
+                        "Cannot restrict {} to a single overlay",
+                        frame.describe(shown_frame)
+                    );
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let progress_bar = ProgressBar::new(generated_documents.len() as u64);
+    in_job_pool(|| {
+        generated_documents
+            .par_iter()
+            .enumerate()
+            .for_each(|(frame_idx, (stem, tex_content))| {
+                if is_compiled(stem, &frame_inputs[frame_idx]) {
+                    trace!("{} is already compiled!", stem);
+                } else {
+                    compile_frame(frame_idx, stem, tex_content, &frame_inputs[frame_idx]);
+                }
+                progress_bar.inc(1);
+            })
+    });
     progress_bar.finish_and_clear();
 
+    if correct_frame_numbers || needs_toc {
+        let frame_inputs = late_inputs();
+        let outdated: Vec<usize> = (0..frames.len())
+            .filter(|&frame_idx| {
+                let stem = &generated_documents[frame_idx].0;
+                cache_subdir.join(format!("{}.pdf", stem)).is_file()
+                    && !is_compiled(stem, &frame_inputs[frame_idx])
+            })
+            .collect();
+        if !outdated.is_empty() {
+            debug!("Correcting the numbers of {} frames", outdated.len());
+            in_job_pool(|| {
+                outdated.par_iter().for_each(|&frame_idx| {
+                    let (stem, tex_content) = &generated_documents[frame_idx];
+                    compile_frame(frame_idx, stem, tex_content, &frame_inputs[frame_idx])
+                })
+            });
+        }
+    }
+
+    let images: Vec<Vec<PathBuf>> = match (args.is_present("png"), Renderer::find()) {
+        (true, Some(renderer)) => {
+            let dpi = value_t!(args, "dpi", u32).unwrap_or(96);
+            in_job_pool(|| {
+                generated_documents
+                    .par_iter()
+                    .enumerate()
+                    .map(|(frame_idx, (stem, _))| {
+                        let pdf = cache_subdir.join(format!("{}.pdf", stem));
+                        match page_count(&cache_subdir.join(format!("{}.log", stem))) {
+                            Some(pages) if pdf.is_file() => {
+                                renderer.render(&pdf, pages, dpi).unwrap_or_else(|err| {
+                                    error!(
+                                        "Failed to render {}: {}",
+                                        frames[frame_idx].describe(frame_idx),
+                                        err
+                                    );
+                                    Vec::new()
+                                })
+                            }
+                            _ => Vec::new(),
+                        }
+                    })
+                    .collect()
+            })
+        }
+        (true, None) => {
+            error!("Rendering PNGs needs pdftoppm (poppler) or mutool (MuPDF) in the PATH");
+            vec![Vec::new(); frames.len()]
+        }
+        _ => vec![Vec::new(); frames.len()],
+    };
+
+    if let Some(report_file) = args.value_of("report") {
+        let reports: Vec<FrameReport> = frames
+            .iter()
+            .zip(generated_documents.iter())
+            .zip(images)
+            .map(|((frame, (stem, _)), images)| FrameReport {
+                frame,
+                pdf: cache_subdir.join(format!("{}.pdf", stem)),
+                pages: page_count(&cache_subdir.join(format!("{}.log", stem))),
+                images,
+            })
+            .collect();
+        let input = input_path.canonicalize().unwrap_or_else(|_| input_path.to_path_buf());
+        if let Err(err) = write(report_file, build_report(&input, &reports)) {
+            error!("Failed to write report \"{}\": {}", report_file, err);
+        }
+    }
+
+    if let Some(overview_file) = args.value_of("overview") {
+        let (columns, rows) = args
+            .value_of("overview-grid")
+            .and_then(parse_grid)
+            .unwrap_or((4, 4));
+        let entries: Vec<OverviewEntry> = frames
+            .iter()
+            .zip(generated_documents.iter())
+            .map(|(frame, (stem, _))| {
+                // the PDFs are linked into the job directory under their plain file names
+                let pdf = format!("{}.pdf", stem);
+                OverviewEntry {
+                    title: frame.title.as_deref(),
+                    page: if args.is_present("overview-last") {
+                        page_count(&cache_subdir.join(format!("{}.log", stem))).unwrap_or(1)
+                    } else {
+                        1
+                    },
+                    pdf: Some(pdf).filter(|pdf| cache_subdir.join(pdf).is_file()),
+                }
+            })
+            .collect();
+        let overview_tex = overview_document(&entries, columns, rows);
+        // the overview is only compiled again when one of its frames or their inputs changed
+        let mut key = md5::Context::new();
+        key.consume(&overview_tex);
+        for inputs in late_inputs() {
+            key.consume(&inputs.stamp);
+        }
+        let overview_dir = cache_subdir.join("overview");
+        let overview_stem = format!("{:x}", key.compute());
+        let overview_pdf = overview_dir.join(format!("{}.pdf", overview_stem));
+        if !overview_pdf.is_file() {
+            info!("Compiling the overview of {} frames", frames.len());
+            let overview_tex_file = overview_dir.join(format!("{}.tex", overview_stem));
+            let write_result = std::fs::create_dir_all(&overview_dir)
+                .and_then(|_| write(&overview_tex_file, &overview_tex));
+            if write_result.is_ok() {
+                let mut compiler = latex_compiler(args, &search_paths, mirror_input);
+                compiler.output_dir = overview_dir.clone();
+                let mut latex_input = LatexInput::new();
+                for (stem, _) in generated_documents.iter() {
+                    let _result =
+                        latex_input.add_file_lazy(cache_subdir.join(format!("{}.pdf", stem)));
+                }
+                if let Err(err) = compiler.run(
+                    &overview_tex_file.to_string_lossy(),
+                    &latex_input,
+                    LatexRunOptions::new(),
+                ) {
+                    error!("Failed to compile the overview!\n{}", err);
+                }
+            } else {
+                error!("Failed to write the overview: {:?}", write_result.err());
+            }
+        }
+        if !link_output(&overview_pdf, overview_file) {
+            error!("Could not create the overview {}", overview_file);
+        }
+    }
+
+    if let Some(export_dir) = args.value_of("export-dir") {
+        let files: Vec<(String, PathBuf)> = frames
+            .iter()
+            .zip(generated_documents.iter())
+            .enumerate()
+            .map(|(frame_idx, (frame, (stem, _)))| {
+                (
+                    export_name(frame, frame_idx, frames.len()),
+                    cache_subdir.join(format!("{}.pdf", stem)),
+                )
+            })
+            .collect();
+        if let Err(err) = export_frames(Path::new(export_dir), &files) {
+            error!("Failed to export the frames to \"{}\": {}", export_dir, err);
+        }
+    }
+
     if args.is_present("pdfunite") {
         let output = command.arg(output_file).output();
 
@@ -333,32 +1540,89 @@ pub fn process_file(input_file: &str, args: &ArgMatches) -> Result<()> {
                 ::std::fs::remove_file(&output_file).expect("Tried to delete previous output file");
         }
 
-        let mut united_tex = format!(
-            "{}\n{}",
-            "\\RequirePackage{pdfpages}", parsed_file.file_content
+        // the frames are replaced by their ranges and still count as frames of the document
+        let replacements: Vec<String> = frames
+            .iter()
+            .zip(generated_documents.iter())
+            .map(|(f, (stem, _))| {
+                let pages = page_count(&cache_subdir.join(format!("{}.log", stem)))
+                    .unwrap_or(f.overlays);
+                f.reinsertion(&format!("{}.pdf", stem), pages)
+            })
+            .collect();
+        // the included files are replaced by copies with their frames replaced
+        let mut renamed_files = Vec::new();
+        let mut latex_input = latex_input.clone();
+        for (range, file, content) in &included {
+            if !frames.iter().any(|f| f.included.as_ref().is_some_and(|(name, _)| name == file)) {
+                continue;
+            }
+            let copy = replace_included_frames(content, file, &frames, &replacements);
+            let name = format!("faster-beamer-input-{:x}", md5::compute(&copy));
+            latex_input.add(&format!("{}.tex", name), copy.into_bytes());
+            renamed_files.push((range.clone(), name));
+        }
+        let content = replace_frames(
+            &parsed_file.file_content,
+            &frames,
+            &replacements,
+            &renamed_files,
         );
-        for (f, (hash, _)) in frames.iter().zip(generated_documents) {
-            let pdf = format!("{:x}.pdf", hash);
-            united_tex = united_tex.replacen(
-                f,
-                &format!("{{\\setbeamercolor{{background canvas}}{{bg=}}\n\\includepdf[pages=-]{{{}}}\n}}", &pdf),
-                1,
-            );
+        let content = match document_begin(&content) {
+            Some(begin) if custom_preamble => united_preamble + &content[begin..],
+            _ => content,
+        };
+        let mut united_tex = format!("{}\n{}", "\\RequirePackage{pdfpages}", content);
+        // the frames that are not included are still frames of the united document
+        if let (Some(labels), Some(begin)) = (
+            args.values_of("only-frames"),
+            document_begin(&united_tex),
+        ) {
+            let labels = labels.collect::<Vec<_>>().join(",");
+            united_tex.insert_str(begin, &format!("\\includeonlyframes{{{}}}\n", labels));
         }
 
-        let united_tex_file = cache_subdir.join("united.tex");
-        let united_pdf = cache_subdir.join("united.pdf");
-        let write_result = write(&united_tex_file, united_tex);
-        if write_result.is_ok() {
-            let mut compiler = LatexCompiler::new()
-                .unwrap()
-                .add_arg("-shell-escape")
-                .add_arg("-interaction=nonstopmode");
-            compiler.working_dir = cache_subdir;
+        // the united document is cached on its source, the inputs of its frames and the files
+        // it includes
+        let mut key = md5::Context::new();
+        key.consume(&united_tex);
+        for inputs in late_inputs() {
+            key.consume(&inputs.stamp);
+        }
+        for file in INCLUDED_FILES.lock().unwrap().iter() {
+            key.consume(std::fs::read(file).unwrap_or_default());
+        }
+        let united_dir = cache_subdir.join("united");
+        let united_stem = format!("{:x}", key.compute());
+        let united_tex_file = united_dir.join(format!("{}.tex", united_stem));
+        let united_pdf = united_dir.join(format!("{}.pdf", united_stem));
+        if united_pdf.is_file() {
+            info!("United document is already compiled");
+        } else {
+            // only the latest united document is kept
+            for entry in std::fs::read_dir(&united_dir).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if path.file_stem().is_some_and(|stem| stem != united_stem.as_str()) {
+                    let _result = std::fs::remove_file(path);
+                }
+            }
+            let write_result = std::fs::create_dir_all(&united_dir)
+                .and_then(|_| write(&united_tex_file, united_tex));
+            if write_result.is_err() {
+                error!("Failed to write united.tex: {:?}", write_result.err());
+                return Err(FasterBeamerError::PdfUniteError);
+            }
+            let mut compiler = latex_compiler(args, &search_paths, mirror_input);
+            compiler.output_dir = united_dir.clone();
+
+            let mut latex_input = latex_input.clone();
+            for (stem, _) in generated_documents.iter() {
+                let _result = latex_input.add_file_lazy(cache_subdir.join(format!("{}.pdf", stem)));
+            }
 
             let compile_result = compiler.run(
                 &united_tex_file.canonicalize().unwrap().to_string_lossy(),
-                &LatexInput::new(),
+                &latex_input,
                 LatexRunOptions::new(),
             );
 
@@ -368,52 +1632,96 @@ pub fn process_file(input_file: &str, args: &ArgMatches) -> Result<()> {
                     compile_result.err().unwrap()
                 );
             }
+        }
 
-            if Path::new(&output_file).is_file() {
-                let _result = ::std::fs::remove_file(&output_file)
-                    .expect("Tried to delete previous output file");
-            }
-            if Path::new(&united_pdf).is_file() {
-                info!("Linking: {:?} -> {:?}", &united_pdf, &output_file);
-                ::symlink::symlink_file(united_pdf, output_file)
-                    .expect("Failed to create symlink to output file.");
-            } else {
-                error!("Compilation failed!");
-                show_error_slide(&cachedir, output_file);
+        if !link_output(&united_pdf, output_file) {
+            error!("Compilation failed!");
+            show_error_slide(&cachedir, output_file);
 
-                *PREVIOUS_FRAMES.lock().unwrap() = frames;
-                return Err(FasterBeamerError::CompileError);
-            }
-        } else {
-            error!("Failed to write united.tex: {:?}", write_result.err());
-            return Err(FasterBeamerError::PdfUniteError);
+            *PREVIOUS_FRAMES.lock().unwrap() = frames;
+            return Err(FasterBeamerError::CompileError);
         }
-    } else {
-        if first_changed_frame == generated_documents.len() {
-            first_changed_frame = 0;
-        }
-        if first_changed_frame < generated_documents.len() {
-            let (hash, _) = generated_documents[first_changed_frame];
-            let compiled_pdf = cache_subdir.join(format!("{:x}.pdf", hash));
+    } else if preview.is_none() && shown_frame < generated_documents.len() {
+        let (stem, _) = &generated_documents[shown_frame];
+        let compiled_pdf = cache_subdir.join(format!("{}.pdf", stem));
 
-            if Path::new(&output_file).is_file() {
-                let _result = ::std::fs::remove_file(&output_file)
-                    .expect("Tried to delete previous output file");
-            }
-            if Path::new(&compiled_pdf).is_file() {
-                info!("Linking: {:?} -> {:?}", &compiled_pdf, &output_file);
-                ::symlink::symlink_file(compiled_pdf, output_file)
-                    .expect("Failed to create symlink to output file.");
-            } else {
-                error!("Compilation failed!");
-                show_error_slide(&cachedir, output_file);
+        if !link_output(&compiled_pdf, output_file) {
+            error!("Compilation failed!");
+            show_error_slide(&cachedir, output_file);
 
-                *PREVIOUS_FRAMES.lock().unwrap() = frames;
-                return Err(FasterBeamerError::CompileError);
-            }
+            *PREVIOUS_FRAMES.lock().unwrap() = frames;
+            return Err(FasterBeamerError::CompileError);
         }
     }
 
     *PREVIOUS_FRAMES.lock().unwrap() = frames;
+    if let Some(job) = full_compile {
+        schedule_full_compile(job);
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_needs_shell_escape() {
+        let banner = concat!(
+            "This is pdfTeX, Version 3.141592653-2.6-1.40.25 (TeX Live 2023)\n",
+            " restricted \\write18 enabled.\n",
+            "entering extended mode\n"
+        );
+        let failure = "! Undefined control sequence.\nl.12 \\foo\n";
+        assert!(!needs_shell_escape(&(banner.to_string() + failure)));
+        assert!(!needs_shell_escape(" \\write18 enabled.\n"));
+
+        let minted = concat!(
+            "! Package minted Error: You must invoke LaTeX with the -shell-escape flag.\n",
+            "\n",
+            "See the minted package documentation for explanation.\n"
+        );
+        assert!(needs_shell_escape(&(banner.to_string() + minted)));
+        assert!(needs_shell_escape(
+            "runsystem(pygmentize -V > version.txt)...disabled (restricted).\n"
+        ));
+        assert!(needs_shell_escape(concat!(
+            "! I can't write on file `x.pyg'.\n",
+            "\\minted@pygmentize ...\\immediate\\write18\n"
+        )));
+    }
+
+    #[test]
+    fn test_article_document() {
+        let content = concat!(
+            "\\documentclass{beamer}\n",
+            "\\begin{document}\n",
+            "\\section{Intro}\n",
+            "Prose that only the article shows.\n",
+            "\\begin{frame}{First}\n",
+            "  Slide\n",
+            "\\end{frame}\n",
+            "\\mode<article>{More prose.}\n",
+            "\\end{document}\n"
+        );
+        let begin = document_begin(content).unwrap();
+        let preamble = set_document_mode(&content[..begin], "article");
+        let article = article_document("fmt", &preamble, &content[begin..]);
+        assert_eq!(
+            article,
+            concat!(
+                "%&fmt\n",
+                "\\documentclass[]{article}\n",
+                "\\usepackage{beamerarticle}\n\n",
+                "\\begin{document}\n",
+                "\\section{Intro}\n",
+                "Prose that only the article shows.\n",
+                "\\begin{frame}{First}\n",
+                "  Slide\n",
+                "\\end{frame}\n",
+                "\\mode<article>{More prose.}\n",
+                "\\end{document}\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//
+// render.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A local program that rasterizes PDFs.
+#[derive(Clone, Debug)]
+pub enum Renderer {
+    /// `pdftoppm` of poppler
+    Pdftoppm(PathBuf),
+    /// `mutool` of MuPDF
+    Mutool(PathBuf),
+}
+
+impl Renderer {
+    /// Finds `pdftoppm` or `mutool` in the `PATH`.
+    pub fn find() -> Option<Renderer> {
+        find_program("pdftoppm")
+            .map(Renderer::Pdftoppm)
+            .or_else(|| find_program("mutool").map(Renderer::Mutool))
+    }
+
+    /// Renders the `pages` of `pdf` with `dpi` to `<stem>-<page>.png` in the directory of the
+    /// PDF and returns the images. The images are only rendered again when the PDF or the
+    /// resolution changed.
+    pub fn render(&self, pdf: &Path, pages: usize, dpi: u32) -> io::Result<Vec<PathBuf>> {
+        let stem = pdf.file_stem().unwrap().to_string_lossy();
+        let dir = pdf.parent().unwrap_or_else(|| Path::new("."));
+        let images: Vec<PathBuf> = (1..=pages)
+            .map(|page| dir.join(format!("{}-{}.png", stem, page)))
+            .collect();
+        let stamp_file = dir.join(format!("{}.png-stamp", stem));
+        let stamp = format!("{} dpi\n", dpi);
+        let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+        let pdf_modified = modified(pdf);
+        if fs::read_to_string(&stamp_file).ok().as_ref() == Some(&stamp)
+            && images.iter().all(|image| modified(image) >= pdf_modified)
+        {
+            return Ok(images);
+        }
+
+        for (page, image) in (1..).zip(&images) {
+            let mut command = match self {
+                Renderer::Pdftoppm(program) => {
+                    let mut command = Command::new(program);
+                    command
+                        .args(["-png", "-singlefile", "-r", &dpi.to_string()])
+                        .args(["-f", &page.to_string(), "-l", &page.to_string()])
+                        .arg(pdf)
+                        // pdftoppm adds the extension itself
+                        .arg(image.with_extension(""));
+                    command
+                }
+                Renderer::Mutool(program) => {
+                    let mut command = Command::new(program);
+                    command
+                        .args(["draw", "-q", "-r", &dpi.to_string(), "-o"])
+                        .arg(image)
+                        .arg(pdf)
+                        .arg(page.to_string());
+                    command
+                }
+            };
+            let output = command.output()?;
+            if !output.status.success() {
+                return Err(io::Error::other(
+                    String::from_utf8_lossy(&output.stderr).into_owned(),
+                ));
+            }
+        }
+        fs::write(stamp_file, stamp)?;
+        Ok(images)
+    }
+}
+
+/// Returns the path of `program` if it is in one of the directories of the `PATH`.
+fn find_program(program: &str) -> Option<PathBuf> {
+    let paths = env::var_os("PATH")?;
+    env::split_paths(&paths)
+        .map(|dir| dir.join(program).with_extension(env::consts::EXE_EXTENSION))
+        .find(|path| path.is_file())
+}
@@ -0,0 +1,119 @@
+//
+// report.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+use crate::beamer::Frame;
+use std::path::{Path, PathBuf};
+
+/// A compiled frame as it is listed in the build report.
+pub struct FrameReport<'a> {
+    pub frame: &'a Frame,
+    /// Cached PDF of the frame
+    pub pdf: PathBuf,
+    /// Number of pages of the PDF if it was compiled
+    pub pages: Option<usize>,
+    /// Images of the pages rendered with `--png`
+    pub images: Vec<PathBuf>,
+}
+
+/// Returns the JSON build report of the `frames` compiled for `input`, so that tools like
+/// presenter consoles can find the PDF, images, title and notes of each frame.
+pub fn build_report(input: &Path, frames: &[FrameReport]) -> String {
+    let entries: Vec<String> = frames
+        .iter()
+        .enumerate()
+        .map(|(frame_idx, report)| {
+            let notes: Vec<String> = report.frame.notes.iter().map(|n| json_string(n)).collect();
+            let images: Vec<String> = report
+                .images
+                .iter()
+                .map(|image| json_string(&image.to_string_lossy()))
+                .collect();
+            format!(
+                concat!(
+                    "    {{\n",
+                    "      \"index\": {},\n",
+                    "      \"title\": {},\n",
+                    "      \"label\": {},\n",
+                    "      \"pdf\": {},\n",
+                    "      \"pages\": {},\n",
+                    "      \"images\": [{}],\n",
+                    "      \"notes\": [{}]\n",
+                    "    }}"
+                ),
+                frame_idx + 1,
+                json_option(report.frame.title.as_deref()),
+                json_option(report.frame.label()),
+                json_string(&report.pdf.to_string_lossy()),
+                report
+                    .pages
+                    .map_or_else(|| "null".to_string(), |pages| pages.to_string()),
+                images.join(", "),
+                notes.join(", ")
+            )
+        })
+        .collect();
+    format!(
+        "{{\n  \"input\": {},\n  \"frames\": [\n{}\n  ]\n}}\n",
+        json_string(&input.to_string_lossy()),
+        entries.join(",\n")
+    )
+}
+
+fn json_option(value: Option<&str>) -> String {
+    value.map_or_else(|| "null".to_string(), json_string)
+}
+
+/// Quotes `value` as a JSON string.
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted += "\\\"",
+            '\\' => quoted += "\\\\",
+            '\n' => quoted += "\\n",
+            '\r' => quoted += "\\r",
+            '\t' => quoted += "\\t",
+            c if (c as u32) < 0x20 => quoted += &format!("\\u{:04x}", c as u32),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beamer::FrameSource;
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string(r"\note{a}"), r#""\\note{a}""#);
+        assert_eq!(json_string("say \"hi\"\n"), r#""say \"hi\"\n""#);
+        assert_eq!(json_string("\u{1}"), r#""\u0001""#);
+    }
+
+    #[test]
+    fn test_build_report() {
+        let content = r"\begin{frame}[label=intro]{Intro}\note{Smile}\end{frame}";
+        let frame = Frame::new(content, 0..content.len(), FrameSource::Fallback);
+        let report = build_report(
+            Path::new("/talk.tex"),
+            &[FrameReport {
+                frame: &frame,
+                pdf: PathBuf::from("/cache/abc.pdf"),
+                pages: None,
+                images: vec![PathBuf::from("/cache/abc-1.png")],
+            }],
+        );
+        assert!(report.starts_with("{\n  \"input\": \"/talk.tex\",\n"));
+        assert!(report.contains("\"title\": \"Intro\",\n"));
+        assert!(report.contains("\"label\": \"intro\",\n"));
+        assert!(report.contains("\"pages\": null,\n"));
+        assert!(report.contains("\"images\": [\"/cache/abc-1.png\"],\n"));
+        assert!(report.contains("\"notes\": [\"Smile\"]\n"));
+    }
+}
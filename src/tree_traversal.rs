@@ -4,33 +4,68 @@
 // Distributed under terms of the MIT license.
 //
 
-use tree_sitter::Node;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use tree_sitter::{Node, Query, QueryCursor};
 
 pub enum TraversalOrder {
     BreadthFirst,
+    DepthFirst,
 }
 
-pub fn get_nodes_of_type(root_node: Node, node_type: String, return_first_only: bool) -> Vec<Node> {
-    let mut stack = vec![root_node];
-    let mut results = Vec::new();
+/// Iterates over the named nodes of a subtree level by level.
+pub struct BreadthFirst<'a> {
+    queue: VecDeque<Node<'a>>,
+}
 
-    while !stack.is_empty() {
-        let current_node = stack.pop().unwrap();
+impl<'a> Iterator for BreadthFirst<'a> {
+    type Item = Node<'a>;
 
-        if current_node.kind() == &node_type[..] {
-            results.push(current_node);
-            if return_first_only {
-                return results;
-            }
+    fn next(&mut self) -> Option<Node<'a>> {
+        let current_node = self.queue.pop_front()?;
+        for i in 0..current_node.named_child_count() {
+            self.queue.push_back(current_node.named_child(i).unwrap());
         }
+        Some(current_node)
+    }
+}
+
+/// Iterates over the named nodes of a subtree in pre-order (document order).
+pub struct DepthFirst<'a> {
+    stack: Vec<Node<'a>>,
+}
 
-        let num_children = current_node.named_child_count();
+impl<'a> Iterator for DepthFirst<'a> {
+    type Item = Node<'a>;
 
-        for i in (0..num_children).rev() {
-            stack.push(current_node.named_child(i).unwrap());
+    fn next(&mut self) -> Option<Node<'a>> {
+        let current_node = self.stack.pop()?;
+        for i in (0..current_node.named_child_count()).rev() {
+            self.stack.push(current_node.named_child(i).unwrap());
         }
+        Some(current_node)
     }
-    results
+}
+
+pub fn breadth_first(root_node: Node) -> BreadthFirst {
+    BreadthFirst {
+        queue: vec![root_node].into(),
+    }
+}
+
+pub fn depth_first(root_node: Node) -> DepthFirst {
+    DepthFirst {
+        stack: vec![root_node],
+    }
+}
+
+pub fn get_nodes_of_type(root_node: Node, node_type: String, return_first_only: bool) -> Vec<Node> {
+    get_children(
+        root_node,
+        &|n| n.kind() == node_type,
+        return_first_only,
+        TraversalOrder::DepthFirst,
+    )
 }
 
 pub fn get_children<'a>(
@@ -39,35 +74,72 @@ pub fn get_children<'a>(
     return_first_only: bool,
     traversal_order: TraversalOrder,
 ) -> Vec<Node<'a>> {
-    let mut stack = vec![root_node];
-    let mut results = Vec::new();
-
-    while !stack.is_empty() {
-        let current_node = stack.pop().unwrap();
+    let nodes: Box<dyn Iterator<Item = Node<'a>>> = match traversal_order {
+        TraversalOrder::BreadthFirst => Box::new(breadth_first(root_node)),
+        TraversalOrder::DepthFirst => Box::new(depth_first(root_node)),
+    };
+    let mut results = nodes.filter(|n| predicate(*n));
+
+    if return_first_only {
+        results.next().into_iter().collect()
+    } else {
+        results.collect()
+    }
+}
 
-        if predicate(current_node) {
-            results.push(current_node);
-            if return_first_only {
-                return results;
+/// Returns all captures of `query` in the subtree of `root_node` as pairs of capture name and
+/// node in document order. If several nodes starting at the same position are captured with
+/// the same name, only the innermost one is kept. This allows to match commands by the text of
+/// wildcard nodes.
+pub fn get_captures<'a, 'q>(
+    query: &'q Query,
+    root_node: Node<'a>,
+    source: &str,
+) -> Vec<(&'q str, Node<'a>)> {
+    // innermost capture by capture index and start byte
+    let mut captures: HashMap<(usize, usize), (usize, u16)> = HashMap::new();
+    let mut cursor = QueryCursor::new();
+
+    for m in cursor.matches(query, root_node, |n| &source.as_bytes()[n.byte_range()]) {
+        for capture in m.captures {
+            let node = capture.node;
+            let end = captures
+                .entry((capture.index as usize, node.start_byte()))
+                .or_insert((node.end_byte(), node.kind_id()));
+            if node.end_byte() < end.0 {
+                *end = (node.end_byte(), node.kind_id());
             }
         }
-
-        let num_children = current_node.named_child_count();
-
-        match traversal_order {
-            TraversalOrder::BreadthFirst => {
-                for i in 0..num_children {
-                    stack.push(current_node.named_child(i).unwrap())
-                }
-            }
-        };
     }
-    results
+    let mut captures: Vec<_> = captures.into_iter().collect();
+    captures.sort_by_key(|((_, start), (end, _))| (*start, *end));
+
+    // nodes of the cursor only live as long as the cursor, so look them up again in the tree
+    captures
+        .into_iter()
+        .filter_map(|((index, start), (end, kind_id))| {
+            find_node(root_node, start..end, kind_id)
+                .map(|node| (query.capture_names()[index].as_str(), node))
+        })
+        .collect()
 }
 
+/// Finds the node with the byte `range` and `kind_id` below `root_node`, starting from the
+/// smallest node that spans the range.
+fn find_node<'a>(root_node: Node<'a>, range: Range<usize>, kind_id: u16) -> Option<Node<'a>> {
+    let mut current_node = root_node.descendant_for_byte_range(range.start, range.end)?;
+    while current_node.kind_id() != kind_id {
+        current_node = current_node.parent()?;
+        if current_node.byte_range() != range {
+            return None;
+        }
+    }
+    Some(current_node)
+}
 
 #[cfg(test)]
 mod tests {
+    use super::*;
     use crate::parsing::ParsedFile;
 
     #[test]
@@ -84,4 +156,21 @@ return 0;
         let parsed = ParsedFile::from_string("main.c".to_string(), source_code.to_string());
         parsed.syntax_tree.root_node();
     }
+
+    #[test]
+    fn test_traversal_orders() {
+        let source_code = include_str!("../tests/corpus/columns.tex");
+        let parsed = ParsedFile::from_string("columns.tex".to_string(), source_code.to_string());
+        let root_node = parsed.syntax_tree.root_node();
+
+        let depth = |n: Node| std::iter::successors(n.parent(), |p| p.parent()).count();
+        let bfs: Vec<_> = breadth_first(root_node).collect();
+        let dfs: Vec<_> = depth_first(root_node).collect();
+
+        assert_eq!(bfs.len(), dfs.len());
+        assert!(bfs.windows(2).all(|w| depth(w[0]) <= depth(w[1])));
+        assert!(dfs
+            .windows(2)
+            .all(|w| w[0].start_byte() <= w[1].start_byte()));
+    }
 }
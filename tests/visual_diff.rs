@@ -0,0 +1,101 @@
+//
+// visual_diff.rs
+// Distributed under terms of the GPLv3 license.
+//
+
+//! Compares the pages of the `--unite` output for the decks in `tests/decks` with a full
+//! compile of the same deck, pixel by pixel. The test needs pdflatex and pdftoppm and only
+//! runs with `cargo test -- --ignored`, which the CI does.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn available(program: &str) -> bool {
+    Command::new(program).arg("-v").output().is_ok()
+}
+
+/// Renders the pages of `pdf` to PPM images and returns their contents.
+fn render(pdf: &Path) -> Vec<Vec<u8>> {
+    let dir = pdf.parent().unwrap().join("pages");
+    fs::create_dir_all(&dir).unwrap();
+    let prefix = dir.join(pdf.file_stem().unwrap());
+    let status = Command::new("pdftoppm")
+        .args(["-r", "40"])
+        .arg(pdf)
+        .arg(&prefix)
+        .status()
+        .unwrap();
+    assert!(status.success(), "pdftoppm failed on {}", pdf.display());
+
+    let mut pages: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "ppm"))
+        .collect();
+    pages.sort();
+    pages.iter().map(|page| fs::read(page).unwrap()).collect()
+}
+
+/// Copies `deck` into a new directory `name` of `root` and returns the copy.
+fn copy_deck(root: &Path, name: &str, deck: &Path) -> PathBuf {
+    let dir = root.join(name);
+    fs::create_dir_all(&dir).unwrap();
+    let copy = dir.join("deck.tex");
+    fs::copy(deck, &copy).unwrap();
+    copy
+}
+
+#[test]
+#[ignore = "needs pdflatex and pdftoppm"]
+fn test_united_frames_match_full_compile() {
+    assert!(
+        available("pdflatex") && available("pdftoppm"),
+        "the visual diff test needs pdflatex and pdftoppm"
+    );
+    let decks = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/decks");
+    for entry in fs::read_dir(decks).unwrap() {
+        let deck = entry.unwrap().path();
+        let root = tempfile::tempdir().unwrap();
+
+        let reference = copy_deck(root.path(), "reference", &deck);
+        // twice for the total frame number and the navigation
+        for _ in 0..2 {
+            let status = Command::new("pdflatex")
+                .args(["-interaction=nonstopmode", "deck.tex"])
+                .current_dir(reference.parent().unwrap())
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "pdflatex failed on {}", deck.display());
+        }
+
+        let split = copy_deck(root.path(), "split", &deck);
+        let united = split.with_file_name("united.pdf");
+        let status = Command::new(env!("CARGO_BIN_EXE_faster-beamer"))
+            .arg(&split)
+            .arg(&united)
+            .args(["--unite", "--frame-numbers", "--full-compile"])
+            .env("XDG_CACHE_HOME", root.path().join("cache"))
+            .status()
+            .unwrap();
+        assert!(status.success(), "faster-beamer failed on {}", deck.display());
+
+        let expected = render(&reference.with_extension("pdf"));
+        let pages = render(&united);
+        assert_eq!(
+            pages.len(),
+            expected.len(),
+            "{} has a different number of pages",
+            deck.display()
+        );
+        for (page, (actual, expected)) in pages.iter().zip(expected.iter()).enumerate() {
+            assert!(
+                actual == expected,
+                "page {} of {} differs from the full compile",
+                page + 1,
+                deck.display()
+            );
+        }
+    }
+}